@@ -0,0 +1,253 @@
+//simple textbook initial conditions, mostly for testing and teaching: a cold uniform sphere, a uniform disk in
+//solid body rotation, random and glass-like particle lattices, and a Zel'dovich perturbed grid.
+
+use std::f64::consts::PI;
+
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+use serde::{Deserialize, Serialize};
+
+use crate::galaxy::random_on_sphere;
+use crate::sim::Body;
+
+const GLASS_ITERATIONS: usize = 40;
+//fraction of the mean spacing a body gets pushed per iteration by a neighbour sitting right on top of it
+const GLASS_STEP: f32 = 0.2;
+
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LatticeKind {
+    //uniformly random, with the usual Poisson clumping
+    #[default]
+    Random,
+    //random, then relaxed until every body keeps its neighbours at about the same distance
+    Glass,
+}
+
+/// A uniform sphere at rest seen from above, the classic cold collapse test.
+pub fn generate_cold_sphere(center_pos: [f32; 2], num_bodies: u32, mass: f32, radius: f32, rng: &mut impl Rng) -> Vec<Body> {
+    let body_mass = mass / num_bodies.max(1) as f32;
+    (0..num_bodies).map(|_| {
+        //uniform in volume
+        let r = radius * rng.gen_range(0.0f32..1.0).cbrt();
+        let [x, y, _] = random_on_sphere(r, rng);
+        Body::new(body_mass, [center_pos[0] + x, center_pos[1] + y], [0.0, 0.0])
+    }).collect()
+}
+
+/// A uniform disk spinning as a solid body, counter-clockwise for positive angular velocities.
+pub fn generate_rotating_disk(center_pos: [f32; 2], num_bodies: u32, mass: f32, radius: f32, angular_velocity: f32, rng: &mut impl Rng) -> Vec<Body> {
+    let body_mass = mass / num_bodies.max(1) as f32;
+    (0..num_bodies).map(|_| {
+        //uniform in area
+        let r = radius * rng.gen_range(0.0f32..1.0).sqrt();
+        let angle = rng.gen_range(0.0..2.0 * std::f32::consts::PI);
+        let [x, y] = [r * angle.cos(), r * angle.sin()];
+        Body::new(body_mass, [center_pos[0] + x, center_pos[1] + y], [-angular_velocity * y, angular_velocity * x])
+    }).collect()
+}
+
+/// Bodies at rest filling a square `size` wide.
+pub fn generate_lattice(center_pos: [f32; 2], num_bodies: u32, mass: f32, size: f32, kind: LatticeKind, rng: &mut impl Rng) -> Vec<Body> {
+    let mut positions: Vec<[f32; 2]> = (0..num_bodies).map(|_| [rng.gen_range(0.0..size), rng.gen_range(0.0..size)]).collect();
+    if let LatticeKind::Glass = kind {
+        relax(&mut positions, size);
+    }
+
+    let body_mass = mass / num_bodies.max(1) as f32;
+    positions.into_iter().map(|[x, y]| {
+        Body::new(body_mass, [center_pos[0] + x - size * 0.5, center_pos[1] + y - size * 0.5], [0.0, 0.0])
+    }).collect()
+}
+
+//pushes close neighbours apart, in a periodic box so the edges don't pile up
+fn relax(positions: &mut [[f32; 2]], size: f32) {
+    if positions.is_empty() {
+        return;
+    }
+    let spacing = size / (positions.len() as f32).sqrt();
+
+    for _ in 0..GLASS_ITERATIONS {
+        let pushes = glass_pushes(positions, size, spacing);
+        for (p, push) in positions.iter_mut().zip(pushes) {
+            p[0] = (p[0] + push[0] * GLASS_STEP * spacing).rem_euclid(size);
+            p[1] = (p[1] + push[1] * GLASS_STEP * spacing).rem_euclid(size);
+        }
+    }
+}
+
+//the push on every body from the neighbours closer than `spacing`, found through a grid of cells `spacing` wide
+fn glass_pushes(positions: &[[f32; 2]], size: f32, spacing: f32) -> Vec<[f32; 2]> {
+    let cells = ((size / spacing) as usize).max(1);
+    let cell_size = size / cells as f32;
+    let cell_of = |p: f32| ((p / cell_size) as usize).min(cells - 1);
+    let wrap = |d: f32| d - size * (d / size).round();
+    //with fewer than 3 cells across, the cells on either side are the same ones and would be visited twice
+    let offsets: Vec<usize> = if cells >= 3 { vec![cells - 1, 0, 1] } else { (0..cells).collect() };
+
+    let mut grid = vec![Vec::new(); cells * cells];
+    for (i, [x, y]) in positions.iter().enumerate() {
+        grid[cell_of(*y) * cells + cell_of(*x)].push(i);
+    }
+
+    positions.iter().enumerate().map(|(i, [x, y])| {
+        let (column, row) = (cell_of(*x), cell_of(*y));
+        let mut push = [0.0, 0.0];
+        for dy in &offsets {
+            for dx in &offsets {
+                for j in &grid[(row + dy) % cells * cells + (column + dx) % cells] {
+                    let [ox, oy] = positions[*j];
+                    let (ddx, ddy) = (wrap(x - ox), wrap(y - oy));
+                    let distance = (ddx * ddx + ddy * ddy).sqrt();
+                    if *j == i || distance >= spacing || distance == 0.0 {
+                        continue;
+                    }
+                    let strength = (1.0 - distance / spacing) / distance;
+                    push[0] += ddx * strength;
+                    push[1] += ddy * strength;
+                }
+            }
+        }
+        push
+    }).collect()
+}
+
+/// A `grid_size` x `grid_size` grid of bodies displaced along a Gaussian random field with a power law power
+/// spectrum P(k) ~ k^spectral_index (the Zel'dovich approximation). `amplitude` is the rms displacement in grid
+/// spacings, and the velocities are the displacements times `growth_rate`. `grid_size` has to be a power of two.
+#[allow(clippy::too_many_arguments)]
+pub fn generate_zeldovich_grid(center_pos: [f32; 2], grid_size: u32, mass: f32, size: f32, spectral_index: f32, amplitude: f32, growth_rate: f32, rng: &mut impl Rng) -> Vec<Body> {
+    let n = grid_size as usize;
+    assert!(n.is_power_of_two(), "the Zel'dovich grid size has to be a power of two, got {n}");
+
+    //white noise, then shaped by the power spectrum in Fourier space. starting from real noise keeps the field real
+    let normal = Normal::new(0.0f64, 1.0).unwrap();
+    let mut density: Vec<[f64; 2]> = (0..n * n).map(|_| [normal.sample(rng), 0.0]).collect();
+    fft_2d(&mut density, n, false);
+
+    let wave_number = |i: usize| {
+        let i = if i < n / 2 { i as f64 } else { i as f64 - n as f64 };
+        2.0 * PI * i / size as f64
+    };
+    //psi(k) = i k / k^2 delta(k), so that the divergence of the displacement is -delta
+    let mut displacement_x = vec![[0.0; 2]; n * n];
+    let mut displacement_y = vec![[0.0; 2]; n * n];
+    for row in 0..n {
+        for column in 0..n {
+            //the Nyquist modes have no partner to keep the field real
+            if (row == 0 && column == 0) || row == n / 2 || column == n / 2 {
+                continue;
+            }
+            let (kx, ky) = (wave_number(column), wave_number(row));
+            let k_sq = kx * kx + ky * ky;
+            let [re, im] = density[row * n + column];
+            let scale = k_sq.sqrt().powf(spectral_index as f64).sqrt() / k_sq;
+            displacement_x[row * n + column] = [-im * kx * scale, re * kx * scale];
+            displacement_y[row * n + column] = [-im * ky * scale, re * ky * scale];
+        }
+    }
+    fft_2d(&mut displacement_x, n, true);
+    fft_2d(&mut displacement_y, n, true);
+
+    let spacing = size / n as f32;
+    let mean_sq = displacement_x.iter().zip(&displacement_y).map(|(x, y)| x[0] * x[0] + y[0] * y[0]).sum::<f64>() / (n * n) as f64;
+    let scale = if mean_sq > 0.0 { (amplitude * spacing) as f64 / mean_sq.sqrt() } else { 0.0 };
+
+    let body_mass = mass / (n * n) as f32;
+    (0..n * n).map(|i| {
+        let (row, column) = (i / n, i % n);
+        let offset = [(displacement_x[i][0] * scale) as f32, (displacement_y[i][0] * scale) as f32];
+        let x = center_pos[0] + (column as f32 + 0.5) * spacing - size * 0.5 + offset[0];
+        let y = center_pos[1] + (row as f32 + 0.5) * spacing - size * 0.5 + offset[1];
+        Body::new(body_mass, [x, y], [offset[0] * growth_rate, offset[1] * growth_rate])
+    }).collect()
+}
+
+//in place, over the rows and then the columns of an n x n grid. the inverse is normalised
+fn fft_2d(data: &mut [[f64; 2]], n: usize, inverse: bool) {
+    for row in data.chunks_mut(n) {
+        fft(row, inverse);
+    }
+    let mut column = vec![[0.0; 2]; n];
+    for c in 0..n {
+        for (r, value) in column.iter_mut().enumerate() {
+            *value = data[r * n + c];
+        }
+        fft(&mut column, inverse);
+        for (r, value) in column.iter().enumerate() {
+            data[r * n + c] = *value;
+        }
+    }
+}
+
+//iterative radix 2 Cooley-Tukey
+fn fft(data: &mut [[f64; 2]], inverse: bool) {
+    let n = data.len();
+    let bits = n.trailing_zeros();
+    if n <= 1 {
+        return;
+    }
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if j > i {
+            data.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut length = 2;
+    while length <= n {
+        let angle = sign * 2.0 * PI / length as f64;
+        for start in (0..n).step_by(length) {
+            for k in 0..length / 2 {
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let [re, im] = data[start + k + length / 2];
+                let twiddled = [re * cos - im * sin, re * sin + im * cos];
+                let even = data[start + k];
+                data[start + k] = [even[0] + twiddled[0], even[1] + twiddled[1]];
+                data[start + k + length / 2] = [even[0] - twiddled[0], even[1] - twiddled[1]];
+            }
+        }
+        length *= 2;
+    }
+
+    if inverse {
+        for value in data.iter_mut() {
+            *value = [value[0] / n as f64, value[1] / n as f64];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn glass_pushes_count_every_neighbour_once() {
+        let size = 1.0;
+        let wrap = |d: f32| d - size * (d / size).round();
+        let rng = &mut SmallRng::seed_from_u64(0);
+        //1, 2 and 7 cells across
+        for num_bodies in [3, 6, 50] {
+            let positions: Vec<[f32; 2]> = (0..num_bodies).map(|_| [rng.gen_range(0.0..size), rng.gen_range(0.0..size)]).collect();
+            let spacing = size / (num_bodies as f32).sqrt();
+
+            let expected = positions.iter().map(|[x, y]| {
+                positions.iter().fold([0.0, 0.0], |push, [ox, oy]| {
+                    let (dx, dy) = (wrap(x - ox), wrap(y - oy));
+                    let distance = (dx * dx + dy * dy).sqrt();
+                    if distance >= spacing || distance == 0.0 {
+                        return push;
+                    }
+                    let strength = (1.0 - distance / spacing) / distance;
+                    [push[0] + dx * strength, push[1] + dy * strength]
+                })
+            });
+            for (push, expected) in glass_pushes(&positions, size, spacing).into_iter().zip(expected) {
+                assert!((push[0] - expected[0]).abs() < 1e-4 && (push[1] - expected[1]).abs() < 1e-4, "{num_bodies} bodies: {push:?} != {expected:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//spherical star clusters: Plummer spheres, King models and Hernquist spheres.
+//positions and velocities are sampled in 3D from each model's isotropic distribution function and then seen from
+//above, so like any projected cluster they are only close to equilibrium under the planar dynamics.
+
+use std::f64::consts::PI;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::galaxy::{random_on_sphere, sample_hernquist_radius};
+use crate::sim::Body;
+
+//in scale radii, bodies further out are resampled
+const PLUMMER_TRUNCATION: f32 = 20.0;
+const HERNQUIST_TRUNCATION: f32 = 20.0;
+//points the distribution function is evaluated at to bound the rejection sampling of speeds
+const SPEED_GRID: usize = 64;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Cluster {
+    pub num_bodies: u32,
+    pub mass: f32,
+    //scale radius for Plummer and Hernquist spheres, core (King) radius for King models
+    pub radius: f32,
+    //primordial mass segregation, 0 places bodies independent of their mass, 1 always puts the heaviest bodies
+    //on the most bound orbits
+    #[serde(default)]
+    pub segregation: f32,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum ClusterModel {
+    Plummer,
+    //w0 is the dimensionless central potential, typically between 3 (loose) and 9 (concentrated)
+    King { w0: f32 },
+    Hernquist,
+}
+
+//a sampled orbit in model units (G = M = radius = 1, except for King models, see KingProfile)
+struct Sample {
+    position: [f32; 3],
+    velocity: [f32; 3],
+    //relative energy, larger is more bound
+    binding_energy: f32,
+}
+
+impl Cluster {
+    /// Generates the cluster centered on the origin, at rest, with all bodies weighing the same.
+    pub fn generate(&self, model: ClusterModel, rng: &mut impl Rng) -> Vec<Body> {
+        let body_mass = self.mass / self.num_bodies.max(1) as f32;
+        self.generate_with_masses(model, &vec![body_mass; self.num_bodies as usize], rng)
+    }
+
+    /// Like `generate`, but with one body per entry of `masses`. The masses are scaled so they add up to `mass`
+    /// and handed out to the sampled orbits according to `segregation`.
+    pub fn generate_with_masses(&self, model: ClusterModel, masses: &[f32], rng: &mut impl Rng) -> Vec<Body> {
+        let num_bodies = masses.len();
+        let (mut samples, model_mass) = match model {
+            ClusterModel::Plummer => ((0..num_bodies).map(|_| sample_plummer(rng)).collect::<Vec<_>>(), 1.0),
+            ClusterModel::Hernquist => ((0..num_bodies).map(|_| sample_hernquist(rng)).collect(), 1.0),
+            ClusterModel::King { w0 } => {
+                let profile = KingProfile::new(w0 as f64);
+                ((0..num_bodies).map(|_| profile.sample(rng)).collect(), profile.mass())
+            }
+        };
+
+        //most bound first, which is the order the heaviest bodies get placed in
+        samples.sort_by(|a, b| b.binding_energy.total_cmp(&a.binding_energy));
+        let masses = segregate(masses, self.mass, self.segregation, rng);
+
+        let velocity_scale = (self.mass / (model_mass * self.radius)).sqrt();
+        samples.iter().zip(masses).map(|(sample, mass)| {
+            let [x, y, _] = sample.position;
+            let [vx, vy, _] = sample.velocity;
+            Body::new(mass, [x * self.radius, y * self.radius], [vx * velocity_scale, vy * velocity_scale])
+        }).collect()
+    }
+}
+
+//hands the masses out to samples sorted from most to least bound. every mass, heaviest first, takes one of the
+//remaining samples, picked with a bias towards the most bound ones that grows with `degree`.
+fn segregate(masses: &[f32], total_mass: f32, degree: f32, rng: &mut impl Rng) -> Vec<f32> {
+    let sum: f32 = masses.iter().sum();
+    let scale = if sum > 0.0 { total_mass / sum } else { 0.0 };
+
+    let mut order: Vec<usize> = (0..masses.len()).collect();
+    order.sort_by(|a, b| masses[*b].total_cmp(&masses[*a]));
+
+    let mut remaining: Vec<usize> = (0..masses.len()).collect();
+    let mut assigned = vec![0.0; masses.len()];
+    for i in order {
+        let pick = if degree >= 1.0 {
+            0
+        } else {
+            let u: f32 = rng.gen_range(0.0..1.0);
+            let bias = 1.0 / (1.0 - degree.max(0.0));
+            ((u.powf(bias) * remaining.len() as f32) as usize).min(remaining.len() - 1)
+        };
+        assigned[remaining.remove(pick)] = masses[i] * scale;
+    }
+    assigned
+}
+
+//Plummer (1911), f(E) ~ E^(7/2), psi = 1 / sqrt(1 + r^2)
+fn sample_plummer(rng: &mut impl Rng) -> Sample {
+    let r = loop {
+        let u: f32 = rng.gen_range(f32::EPSILON..1.0);
+        let r = 1.0 / (u.powf(-2.0 / 3.0) - 1.0).sqrt();
+        if r < PLUMMER_TRUNCATION {
+            break r;
+        }
+    };
+    let psi = 1.0 / (1.0 + r as f64 * r as f64).sqrt();
+    sample_orbit(r, psi, |e| e.powf(3.5), rng)
+}
+
+//Hernquist (1990), psi = 1 / (1 + r)
+fn sample_hernquist(rng: &mut impl Rng) -> Sample {
+    let r = sample_hernquist_radius(1.0, HERNQUIST_TRUNCATION, rng);
+    let psi = 1.0 / (1.0 + r as f64);
+    sample_orbit(r, psi, hernquist_distribution, rng)
+}
+
+fn hernquist_distribution(e: f64) -> f64 {
+    let q = e.sqrt().min(1.0 - 1e-9);
+    let q_sq = q * q;
+    let bracket = 3.0 * q.asin() + q * (1.0 - q_sq).sqrt() * (1.0 - 2.0 * q_sq) * (8.0 * q_sq * q_sq - 8.0 * q_sq - 3.0);
+    (bracket / (1.0 - q_sq).powf(2.5)).max(0.0)
+}
+
+//places a body at radius r in a random direction, with a speed drawn from v^2 f(psi - v^2 / 2)
+fn sample_orbit(r: f32, psi: f64, distribution: impl Fn(f64) -> f64, rng: &mut impl Rng) -> Sample {
+    let escape_speed = (2.0 * psi).sqrt();
+    let density = |v: f64| v * v * distribution((psi - 0.5 * v * v).max(0.0));
+
+    let peak = (1..SPEED_GRID).map(|i| density(escape_speed * i as f64 / SPEED_GRID as f64)).fold(0.0, f64::max);
+    let speed = if peak > 0.0 {
+        //the grid can miss the very top of the peak
+        let bound = 1.5 * peak;
+        loop {
+            let v = rng.gen_range(0.0..escape_speed);
+            if rng.gen_range(0.0..bound) < density(v) {
+                break v;
+            }
+        }
+    } else {
+        0.0
+    };
+
+    Sample {
+        position: random_on_sphere(r, rng),
+        velocity: random_on_sphere(speed as f32, rng),
+        binding_energy: (psi - 0.5 * speed * speed) as f32,
+    }
+}
+
+//King (1966) model, tabulated by integrating Poisson's equation outwards until the potential reaches zero at the
+//tidal radius. units are G = 1, sigma = 1 and a core radius of 1, the total mass falls out of the integration.
+struct KingProfile {
+    //[radius, dimensionless potential W, enclosed mass]
+    table: Vec<[f64; 3]>,
+}
+
+impl KingProfile {
+    fn new(w0: f64) -> Self {
+        let central_density = king_density(w0);
+        //9 rho / rho_0 with the core radius r_0^2 = 9 sigma^2 / (4 pi G rho_0) set to 1
+        let derivatives = |r: f64, [w, dw, _]: [f64; 3]| {
+            let density = king_density(w.max(0.0)) / central_density;
+            [dw, -2.0 * dw / r - 9.0 * density, 9.0 * r * r * density]
+        };
+
+        //start just off the center, where W is flat
+        let mut r = 1e-6;
+        let mut state = [w0, 0.0, 0.0];
+        let mut table = vec![[0.0, w0, 0.0]];
+        while state[0] > 0.0 {
+            let h = 1e-3 * (1.0 + r);
+            let add = |a: [f64; 3], b: [f64; 3], s: f64| [a[0] + b[0] * s, a[1] + b[1] * s, a[2] + b[2] * s];
+            let k1 = derivatives(r, state);
+            let k2 = derivatives(r + 0.5 * h, add(state, k1, 0.5 * h));
+            let k3 = derivatives(r + 0.5 * h, add(state, k2, 0.5 * h));
+            let k4 = derivatives(r + h, add(state, k3, h));
+            state = std::array::from_fn(|i| state[i] + h / 6.0 * (k1[i] + 2.0 * k2[i] + 2.0 * k3[i] + k4[i]));
+            r += h;
+            table.push([r, state[0].max(0.0), state[2]]);
+        }
+
+        Self { table }
+    }
+
+    fn mass(&self) -> f32 {
+        self.table[self.table.len() - 1][2] as f32
+    }
+
+    fn sample(&self, rng: &mut impl Rng) -> Sample {
+        let target = rng.gen_range(0.0..1.0) * self.table[self.table.len() - 1][2];
+        let upper = self.table.partition_point(|row| row[2] < target).clamp(1, self.table.len() - 1);
+        let [r0, w0, m0] = self.table[upper - 1];
+        let [r1, w1, m1] = self.table[upper];
+        let t = if m1 > m0 { (target - m0) / (m1 - m0) } else { 0.0 };
+
+        let r = r0 + (r1 - r0) * t;
+        let w = w0 + (w1 - w0) * t;
+        sample_orbit(r as f32, w, |e| e.exp() - 1.0, rng)
+    }
+}
+
+//density of a King model at dimensionless potential w, up to a constant
+fn king_density(w: f64) -> f64 {
+    //the two terms nearly cancel for small w
+    (w.exp() * erf(w.sqrt()) - (4.0 * w / PI).sqrt() * (1.0 + 2.0 * w / 3.0)).max(0.0)
+}
+
+//Abramowitz and Stegun 7.1.26, good to about 1e-7
+fn erf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let polynomial = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    (1.0 - polynomial * (-x * x).exp()).copysign(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::diagnostics::testing::{direct_potentials, sampling_noise};
+    use crate::diagnostics::Diagnostics;
+    use crate::galaxy::length;
+
+    #[test]
+    fn clusters_are_centred_and_in_virial_equilibrium() {
+        //an isotropic cluster in equilibrium has 2K = |W| in 3D. seen from above the bodies keep 2 of their 3 velocity
+        //components, and the projected separations make the potential pi/2 times deeper on average
+        let projected_ratio = 4.0 / (3.0 * std::f32::consts::PI);
+
+        let cluster = Cluster { num_bodies: 2_000, mass: 500.0, radius: 3.0, segregation: 0.0 };
+        for model in [ClusterModel::Plummer, ClusterModel::King { w0: 3.0 }, ClusterModel::King { w0: 7.0 }, ClusterModel::Hernquist] {
+            let bodies = cluster.generate(model, &mut SmallRng::seed_from_u64(0));
+            assert_eq!(bodies.len(), 2_000);
+            let diagnostics = Diagnostics::from_bodies(&bodies, &direct_potentials(&bodies));
+            assert!((diagnostics.mass - cluster.mass).abs() < 1e-3, "{model:?}: mass {}", diagnostics.mass);
+
+            let offset = length(diagnostics.center_of_mass);
+            let position_noise = sampling_noise(&bodies, |body| body.position);
+            assert!(offset < 4.0 * position_noise, "{model:?}: center of mass off by {offset}, noise {position_noise}");
+
+            let drift = length(diagnostics.momentum) / diagnostics.mass;
+            let velocity_noise = sampling_noise(&bodies, |body| body.velocity);
+            assert!(drift < 4.0 * velocity_noise, "{model:?}: bulk velocity {drift}, noise {velocity_noise}");
+
+            //the truncation cuts the far tail of the Hernquist sphere, which leaves it a little cooler
+            let ratio = diagnostics.virial_ratio();
+            assert!((ratio - projected_ratio).abs() < 0.15 * projected_ratio, "{model:?}: virial ratio {ratio}");
+        }
+    }
+}
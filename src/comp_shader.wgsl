@@ -2,8 +2,14 @@
 //https://github.com/Canleskis/particular/blob/main/particular/src/compute_method/gpu_compute/compute.wgsl 
 
 const kSoftening = 1.0;
-const DT = 0.01;
 
+struct SimParams {
+    num_bodies: u32,
+    dt: f32,
+    escape_radius: f32,
+    gravitational_constant: f32,
+    min_distance_sq: f32,
+}
 
 @group(0)
 @binding(0)
@@ -14,16 +20,30 @@ var <storage, read> masses: array<f32>;
 @group(0)
 @binding(2)
 var <storage, read_write> velocities: array<vec2<f32>>;
+@group(0)
+@binding(3)
+var<uniform> params: SimParams;
+//specific potential of every body, used for the escape criterion
+@group(0)
+@binding(4)
+var <storage, read_write> potentials: array<f32>;
+//which ensemble member every body belongs to, and the [start, end) body range of every member
+@group(0)
+@binding(5)
+var <storage, read> systems: array<u32>;
+@group(0)
+@binding(6)
+var <storage, read> ranges: array<vec2<u32>>;
 
-@compute
-@workgroup_size(256, 1, 1)
-fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
-    let i = global_invocation_id.x;
-
+//updates the velocity and potential of body i from the current positions and returns the new velocity
+fn kick_body(i: u32) -> vec2<f32> {
     let pos_from = positions[i];
     var acceleration = vec2<f32>(0.0);
+    var potential = 0.0;
 
-    for (var j = 0u; j < arrayLength(&positions); j++) {
+    //bodies only feel the other bodies of their own system
+    let range = ranges[systems[i]];
+    for (var j = range.x; j < range.y; j++) {
         let pos_to = positions[j];
 
         let dirx = pos_to.x - pos_from.x;
@@ -36,17 +56,59 @@ fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
         let ax = dirx * inv;
         let ay = diry * inv;
 
-        if (norm > 0.3) {
+        if (norm > params.min_distance_sq) {
             acceleration.x += ax;
             acceleration.y += ay;
+            potential -= masses[j] * inverseSqrt(norm);
         }
     }
 
+    potentials[i] = potential * params.gravitational_constant;
+
     let velocity = velocities[i];
-    let new_velocity = velocity + acceleration * DT;
+    let new_velocity = velocity + acceleration * params.gravitational_constant * params.dt;
 
     velocities[i] = new_velocity;
+    return new_velocity;
+}
 
-    let new_pos = pos_from + new_velocity * DT;
+//kick and drift in one pass. other invocations may already have moved their body by the time body i reads it,
+//so the result depends on how the workgroups get scheduled.
+@compute
+@workgroup_size(256, 1, 1)
+fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let i = global_invocation_id.x;
+    //the buffers are allocated with spare capacity, only the first num_bodies are alive
+    if (i >= params.num_bodies) {
+        return;
+    }
+
+    let new_velocity = kick_body(i);
+
+    let new_pos = positions[i] + new_velocity * params.dt;
     positions[i] = new_pos;
 }
+
+//deterministic mode splits the step in two dispatches, so every force is computed from the positions at the start of
+//the tick and always summed in the same order.
+@compute
+@workgroup_size(256, 1, 1)
+fn kick(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let i = global_invocation_id.x;
+    if (i >= params.num_bodies) {
+        return;
+    }
+
+    kick_body(i);
+}
+
+@compute
+@workgroup_size(256, 1, 1)
+fn drift(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let i = global_invocation_id.x;
+    if (i >= params.num_bodies) {
+        return;
+    }
+
+    positions[i] += velocities[i] * params.dt;
+}
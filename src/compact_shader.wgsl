@@ -0,0 +1,122 @@
+//stream compaction of the body buffers.
+//scan runs as a single workgroup, every thread owns a contiguous block of bodies, so the
+//relative order of the surviving bodies is preserved.
+//scatter_* then moves every body attribute into the scratch buffer using the computed destinations.
+//rebuild_ranges finally recomputes the body range of every ensemble member from the compacted system indices.
+
+struct SimParams {
+    num_bodies: u32,
+    dt: f32,
+    escape_radius: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: SimParams;
+@group(0) @binding(1)
+var<storage, read> flags: array<u32>;
+@group(0) @binding(2)
+var<storage, read_write> destinations: array<u32>;
+@group(0) @binding(3)
+var<storage, read_write> active_count: array<u32>;
+
+//attributes are moved as raw bits, so one entry point works for f32 and u32 buffers alike
+@group(1) @binding(0)
+var<storage, read> src_scalar: array<u32>;
+@group(1) @binding(1)
+var<storage, read_write> dst_scalar: array<u32>;
+@group(1) @binding(2)
+var<storage, read> src_vec: array<vec2<u32>>;
+@group(1) @binding(3)
+var<storage, read_write> dst_vec: array<vec2<u32>>;
+
+@group(1) @binding(4)
+var<storage, read> systems: array<u32>;
+@group(1) @binding(5)
+var<storage, read_write> ranges: array<vec2<u32>>;
+
+const WORKGROUP_SIZE = 256u;
+const REMOVED = 0xffffffffu;
+
+var<workgroup> sums: array<u32, WORKGROUP_SIZE>;
+
+@compute
+@workgroup_size(256, 1, 1)
+fn scan(@builtin(local_invocation_index) t: u32) {
+    let n = params.num_bodies;
+    let per_thread = (n + WORKGROUP_SIZE - 1u) / WORKGROUP_SIZE;
+    let start = min(t * per_thread, n);
+    let end = min(start + per_thread, n);
+
+    var count = 0u;
+    for (var i = start; i < end; i++) {
+        if (flags[i] != 0u) {
+            count += 1u;
+        }
+    }
+    sums[t] = count;
+    workgroupBarrier();
+
+    //inclusive scan over the per-thread counts
+    for (var offset = 1u; offset < WORKGROUP_SIZE; offset *= 2u) {
+        var value = 0u;
+        if (t >= offset) {
+            value = sums[t - offset];
+        }
+        workgroupBarrier();
+        sums[t] += value;
+        workgroupBarrier();
+    }
+
+    var next = sums[t] - count;
+    for (var i = start; i < end; i++) {
+        if (flags[i] != 0u) {
+            destinations[i] = next;
+            next += 1u;
+        } else {
+            destinations[i] = REMOVED;
+        }
+    }
+
+    if (t == WORKGROUP_SIZE - 1u) {
+        active_count[0] = sums[t];
+    }
+}
+
+@compute
+@workgroup_size(256, 1, 1)
+fn scatter_scalar(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let i = global_invocation_id.x;
+    if (i >= params.num_bodies || destinations[i] == REMOVED) {
+        return;
+    }
+    dst_scalar[destinations[i]] = src_scalar[i];
+}
+
+@compute
+@workgroup_size(256, 1, 1)
+fn scatter_vec(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let i = global_invocation_id.x;
+    if (i >= params.num_bodies || destinations[i] == REMOVED) {
+        return;
+    }
+    dst_vec[destinations[i]] = src_vec[i];
+}
+
+//expects the ranges to be cleared beforehand, so members without any bodies left end up empty
+@compute
+@workgroup_size(256, 1, 1)
+fn rebuild_ranges(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let i = global_invocation_id.x;
+    let n = active_count[0];
+    if (i >= n) {
+        return;
+    }
+
+    let system = systems[i];
+    if (i == 0u || systems[i - 1u] != system) {
+        ranges[system].x = i;
+    }
+    if (i == n - 1u || systems[i + 1u] != system) {
+        ranges[system].y = i + 1u;
+    }
+}
@@ -0,0 +1,299 @@
+//projected surface density maps for science use: the mass of the bodies binned into pixels on the gpu, in float
+//precision and linear, framed the same way as the rendered frames, and saved as FITS images.
+//the simulation is flat, so the projection is always along z.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use wgpu::{BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, Buffer, BufferAddress, BufferDescriptor, BufferUsages, ComputePipeline, ComputePipelineDescriptor, Device, include_wgsl, PipelineLayoutDescriptor, Queue};
+
+use crate::camera::Camera;
+use crate::sim::{read_buffer, storage_layout_entry, uniform_layout_entry, SimState};
+use crate::units::UnitSystem;
+
+const WORKGROUP_SIZE: u32 = 256;
+const FITS_BLOCK_SIZE: usize = 2880;
+const FITS_CARD_SIZE: usize = 80;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MapParams {
+    center: [f32; 2],
+    pixel_scale: f32,
+    x_sign: f32,
+    width: u32,
+    height: u32,
+    num_bodies: u32,
+    inv_pixel_area: f32,
+}
+
+/// What part of the simulation plane a map covers.
+#[derive(Copy, Clone, Debug)]
+pub struct Framing {
+    pub center: [f32; 2],
+    //world units per pixel
+    pub pixel_scale: f32,
+    //world x grows to the left, as it does in the rendered frames
+    pub mirrored: bool,
+}
+
+impl Framing {
+    /// The framing of a camera looking down at the plane, for a map `height` pixels high. Matches the rendered
+    /// frames exactly when the camera looks at the origin.
+    pub(crate) fn from_camera(camera: &Camera, height: u32) -> Self {
+        let view_projection = camera.build_view_projection_matrix();
+        let project = |x: f32, y: f32| {
+            let clip = view_projection * cgmath::Vector4::new(x, y, 0.0, 1.0);
+            [clip.x / clip.w, clip.y / clip.w]
+        };
+
+        let center = [camera.target.x, camera.target.y];
+        let origin = project(center[0], center[1]);
+        let unit_x = project(center[0] + 1.0, center[1]);
+        let unit_y = project(center[0], center[1] + 1.0);
+
+        //normalized device coordinates span 2 over the height of the frame
+        let ndc_per_unit = unit_y[1] - origin[1];
+        Self {
+            center,
+            pixel_scale: 2.0 / (ndc_per_unit * height as f32),
+            mirrored: unit_x[0] < origin[0],
+        }
+    }
+}
+
+pub struct DensityMap {
+    pub width: u32,
+    pub height: u32,
+    pub framing: Framing,
+    pub time: f32,
+    pub units: UnitSystem,
+    //surface density, row by row from the bottom of the map
+    pub data: Vec<f32>,
+}
+
+pub struct DensityMapper {
+    pub pipeline: ComputePipeline,
+    pub bind_group_layout: BindGroupLayout,
+    pub params_buffer: Buffer,
+    pub map_buffer: Buffer,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl DensityMapper {
+    pub fn new(device: &Device, width: u32, height: u32) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Density map bind group layout"),
+            entries: &[
+                //params
+                uniform_layout_entry(0),
+                //positions
+                storage_layout_entry(1, true),
+                //masses
+                storage_layout_entry(2, true),
+                //map
+                storage_layout_entry(3, false),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Density map pipeline layout"),
+            bind_group_layouts: &[
+                &bind_group_layout
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let shader = device.create_shader_module(include_wgsl!("density_shader.wgsl"));
+
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Density map compute pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+
+        let params_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Density map params buffer"),
+            size: std::mem::size_of::<MapParams>() as BufferAddress,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let map_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Density map buffer"),
+            size: width as BufferAddress * height as BufferAddress * std::mem::size_of::<f32>() as BufferAddress,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            params_buffer,
+            map_buffer,
+            width,
+            height,
+        }
+    }
+
+    /// Bins the live bodies of every system into a map.
+    pub async fn project(&self, device: &Device, queue: &Queue, sim_state: &SimState, framing: Framing) -> DensityMap {
+        let params = MapParams {
+            center: framing.center,
+            pixel_scale: framing.pixel_scale,
+            x_sign: if framing.mirrored { -1.0 } else { 1.0 },
+            width: self.width,
+            height: self.height,
+            num_bodies: sim_state.num_bodies(),
+            inv_pixel_area: 1.0 / (framing.pixel_scale * framing.pixel_scale),
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[params]));
+
+        //the body buffers get reallocated as the simulation grows, so this can't be kept around
+        let buffers = &sim_state.buffers;
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Density map bind group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: self.params_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: buffers.positions_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: buffers.input_masses.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: self.map_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor { label: Some("Density map encoder") },
+        );
+        encoder.clear_buffer(&self.map_buffer, 0, None);
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&Default::default());
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.dispatch_workgroups(params.num_bodies.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+        queue.submit(Some(encoder.finish()));
+
+        DensityMap {
+            width: self.width,
+            height: self.height,
+            framing,
+            time: sim_state.time,
+            units: sim_state.units,
+            data: read_buffer::<f32>(device, queue, &self.map_buffer, self.map_buffer.size()).await,
+        }
+    }
+}
+
+impl DensityMap {
+    pub fn save_fits(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_fits(&mut writer)?;
+        writer.flush()
+    }
+
+    /// A single 32 bit float image, with a linear world coordinate system for the simulation plane.
+    pub fn write_fits(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        let (length_unit, mass_unit, time_unit) = match self.units {
+            UnitSystem::Code => ("code", "code", "code"),
+            units => (units.length_name().trim(), units.mass_name().trim(), units.time_name().trim()),
+        };
+        let x_step = if self.framing.mirrored { -self.framing.pixel_scale } else { self.framing.pixel_scale };
+
+        let mut header = String::new();
+        let mut card = |key: &str, value: String, comment: &str| {
+            //strings start right after the equals sign, everything else ends in column 30
+            let card = if value.starts_with('\'') {
+                format!("{key:<8}= {value:<20} / {comment}")
+            } else {
+                format!("{key:<8}= {value:>20} / {comment}")
+            };
+            header.push_str(&format!("{:<1$.1$}", card, FITS_CARD_SIZE));
+        };
+        let text = |value: &str| format!("'{value:<8}'");
+
+        card("SIMPLE", "T".into(), "conforms to FITS standard");
+        card("BITPIX", "-32".into(), "32 bit floats");
+        card("NAXIS", "2".into(), "number of axes");
+        card("NAXIS1", self.width.to_string(), "pixels along x");
+        card("NAXIS2", self.height.to_string(), "pixels along y");
+        card("BUNIT", text(&format!("{mass_unit}/{length_unit}**2")), "projected surface density");
+        card("CTYPE1", text("X"), "simulation x");
+        card("CTYPE2", text("Y"), "simulation y");
+        card("CUNIT1", text(length_unit), "unit of x");
+        card("CUNIT2", text(length_unit), "unit of y");
+        card("CRPIX1", format!("{:.1}", self.width as f32 * 0.5 + 0.5), "reference pixel, the map center");
+        card("CRPIX2", format!("{:.1}", self.height as f32 * 0.5 + 0.5), "reference pixel, the map center");
+        card("CRVAL1", format!("{:E}", self.framing.center[0]), "x at the reference pixel");
+        card("CRVAL2", format!("{:E}", self.framing.center[1]), "y at the reference pixel");
+        card("CDELT1", format!("{x_step:E}"), "x step per pixel");
+        card("CDELT2", format!("{:E}", self.framing.pixel_scale), "y step per pixel");
+        card("PIXSCALE", format!("{:E}", self.framing.pixel_scale), "pixel size in length units");
+        card("PROJAXIS", text("Z"), "projected along this axis");
+        card("TIME", format!("{:E}", self.time), "simulation time");
+        card("TIMEUNIT", text(time_unit), "unit of time");
+        card("UNITSYS", text(&format!("{:?}", self.units).to_lowercase()), "unit system of the run");
+        header.push_str(&format!("{:<1$}", "END", FITS_CARD_SIZE));
+        header.push_str(&" ".repeat(header.len().next_multiple_of(FITS_BLOCK_SIZE) - header.len()));
+        writer.write_all(header.as_bytes())?;
+
+        let data = self.data.iter().flat_map(|v| v.to_be_bytes()).collect::<Vec<_>>();
+        writer.write_all(&data)?;
+        writer.write_all(&vec![0; data.len().next_multiple_of(FITS_BLOCK_SIZE) - data.len()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fits_header_is_in_cards_and_blocks() {
+        let map = DensityMap {
+            width: 3,
+            height: 2,
+            framing: Framing { center: [1.0, -2.0], pixel_scale: 0.5, mirrored: true },
+            time: 4.0,
+            units: UnitSystem::Galactic,
+            data: vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0],
+        };
+        let mut bytes = Vec::new();
+        map.write_fits(&mut bytes).unwrap();
+
+        //one block of header and one of data for a map this small
+        assert_eq!(bytes.len(), 2 * FITS_BLOCK_SIZE);
+        let (header, data) = bytes.split_at(FITS_BLOCK_SIZE);
+        assert!(header.is_ascii());
+        let cards = header.chunks(FITS_CARD_SIZE).map(|card| std::str::from_utf8(card).unwrap()).collect::<Vec<_>>();
+        assert!(cards[0].starts_with("SIMPLE  =                    T"));
+        assert!(cards[1].starts_with("BITPIX  =                  -32"));
+        assert!(cards.iter().any(|card| card.starts_with("NAXIS1  =                    3")));
+        assert!(cards.iter().any(|card| card.starts_with("NAXIS2  =                    2")));
+
+        //everything after END is blank padding
+        let end = cards.iter().position(|card| card.trim_end() == "END").unwrap();
+        assert!(cards[end + 1..].iter().all(|card| card.trim().is_empty()));
+
+        let values = data.chunks(4).take(6).map(|v| f32::from_be_bytes(v.try_into().unwrap())).collect::<Vec<_>>();
+        assert_eq!(values, map.data);
+        assert!(data[6 * 4..].iter().all(|&byte| byte == 0));
+    }
+}
@@ -0,0 +1,51 @@
+//deposits the mass of every body into the pixel of the density map it falls in, divided by the pixel area.
+//wgsl has no float atomics, so the adds are compare exchange loops on the bits of the float.
+
+struct MapParams {
+    center: vec2<f32>,
+    //world units per pixel
+    pixel_scale: f32,
+    //-1 when world x grows to the left of the map, like in the rendered frames
+    x_sign: f32,
+    width: u32,
+    height: u32,
+    num_bodies: u32,
+    inv_pixel_area: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: MapParams;
+@group(0) @binding(1)
+var<storage, read> positions: array<vec2<f32>>;
+@group(0) @binding(2)
+var<storage, read> masses: array<f32>;
+@group(0) @binding(3)
+var<storage, read_write> map: array<atomic<u32>>;
+
+@compute
+@workgroup_size(256)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let i = global_id.x;
+    if i >= params.num_bodies {
+        return;
+    }
+
+    let offset = (positions[i] - params.center) / params.pixel_scale;
+    let column = floor(offset.x * params.x_sign + f32(params.width) * 0.5);
+    //row 0 is at the bottom
+    let row = floor(offset.y + f32(params.height) * 0.5);
+    if column < 0.0 || row < 0.0 || column >= f32(params.width) || row >= f32(params.height) {
+        return;
+    }
+
+    let pixel = u32(row) * params.width + u32(column);
+    let value = masses[i] * params.inv_pixel_area;
+    var old = atomicLoad(&map[pixel]);
+    loop {
+        let exchange = atomicCompareExchangeWeak(&map[pixel], old, bitcast<u32>(bitcast<f32>(old) + value));
+        if exchange.exchanged {
+            break;
+        }
+        old = exchange.old_value;
+    }
+}
@@ -0,0 +1,89 @@
+use crate::sim::Body;
+
+//conserved quantities of a single ensemble member (or of the whole simulation, outside of ensemble mode)
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Diagnostics {
+    pub num_bodies: u32,
+    pub mass: f32,
+    pub kinetic_energy: f32,
+    //the potentials are the ones computed during the last tick, so this lags the kinetic energy by one step
+    pub potential_energy: f32,
+    pub center_of_mass: [f32; 2],
+    pub momentum: [f32; 2],
+}
+
+impl Diagnostics {
+    pub fn from_bodies(bodies: &[Body], potentials: &[f32]) -> Self {
+        let mut mass = 0.0f64;
+        let mut kinetic_energy = 0.0f64;
+        let mut potential_energy = 0.0f64;
+        let mut weighted_position = [0.0f64; 2];
+        let mut momentum = [0.0f64; 2];
+
+        for (body, potential) in bodies.iter().zip(potentials) {
+            let m = body.mass as f64;
+            let [vx, vy] = body.velocity.map(|v| v as f64);
+            mass += m;
+            kinetic_energy += 0.5 * m * (vx * vx + vy * vy);
+            //every pair is counted twice in the per-body potentials
+            potential_energy += 0.5 * m * *potential as f64;
+            weighted_position[0] += m * body.position[0] as f64;
+            weighted_position[1] += m * body.position[1] as f64;
+            momentum[0] += m * vx;
+            momentum[1] += m * vy;
+        }
+
+        let center_of_mass = if mass > 0.0 {
+            weighted_position.map(|p| (p / mass) as f32)
+        } else {
+            [0.0; 2]
+        };
+
+        Self {
+            num_bodies: bodies.len() as u32,
+            mass: mass as f32,
+            kinetic_energy: kinetic_energy as f32,
+            potential_energy: potential_energy as f32,
+            center_of_mass,
+            momentum: momentum.map(|p| p as f32),
+        }
+    }
+
+    pub fn total_energy(&self) -> f32 {
+        self.kinetic_energy + self.potential_energy
+    }
+
+    //2K/|W|, 1 for a system in equilibrium
+    pub fn virial_ratio(&self) -> f32 {
+        2.0 * self.kinetic_energy / self.potential_energy.abs()
+    }
+}
+
+//checks shared by the tests of the generators
+#[cfg(test)]
+pub(crate) mod testing {
+    use crate::sim::Body;
+
+    /// How far the mass weighted mean of `value` typically strays from zero when the bodies are placed independently,
+    /// so a generator whose centre or bulk velocity is off by more than a few of these is biased.
+    pub(crate) fn sampling_noise(bodies: &[Body], value: impl Fn(&Body) -> [f32; 2]) -> f32 {
+        let mass: f64 = bodies.iter().map(|body| body.mass as f64).sum();
+        let spread: f64 = bodies.iter().map(|body| {
+            let [x, y] = value(body).map(|v| v as f64);
+            (body.mass as f64).powi(2) * (x * x + y * y)
+        }).sum();
+        (spread.sqrt() / mass) as f32
+    }
+
+    /// The potential of every body from all the others, summed directly with G = 1 and no softening.
+    pub(crate) fn direct_potentials(bodies: &[Body]) -> Vec<f32> {
+        bodies.iter().enumerate().map(|(i, body)| {
+            let potential: f64 = bodies.iter().enumerate().filter(|(j, _)| *j != i).map(|(_, other)| {
+                let dx = (other.position[0] - body.position[0]) as f64;
+                let dy = (other.position[1] - body.position[1]) as f64;
+                -(other.mass as f64) / (dx * dx + dy * dy).sqrt()
+            }).sum();
+            potential as f32
+        }).collect()
+    }
+}
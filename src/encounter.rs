@@ -0,0 +1,169 @@
+//setting up galaxy encounters: putting systems on Keplerian approach orbits and tilting their disks.
+//the orbit is that of two point masses, so it only holds until the systems start overlapping.
+
+use serde::{Deserialize, Serialize};
+
+use crate::sim::Body;
+
+/// A two body orbit, with the pericenter along the direction given by `angle` and the systems going
+/// around each other counter-clockwise.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Orbit {
+    pub pericenter: f32,
+    //0 circular, below 1 bound, 1 parabolic, above 1 hyperbolic
+    pub eccentricity: f32,
+    //distance between the centers of mass to start at, before the pericenter passage. clamped to the apocenter
+    //for bound orbits
+    pub separation: f32,
+    //counter-clockwise from the x axis, in degrees
+    #[serde(default)]
+    pub angle: f32,
+}
+
+impl Orbit {
+    /// Position and velocity of the secondary relative to the primary, `mu` being G times the combined mass.
+    pub fn relative_state(&self, mu: f32) -> ([f32; 2], [f32; 2]) {
+        let e = self.eccentricity.max(0.0);
+        let semi_latus_rectum = self.pericenter * (1.0 + e);
+        let separation = if e < 1.0 {
+            self.separation.clamp(self.pericenter, semi_latus_rectum / (1.0 - e))
+        } else {
+            self.separation.max(self.pericenter)
+        };
+
+        //true anomaly, negative so the systems are still approaching each other
+        let anomaly = if e > 0.0 {
+            -((semi_latus_rectum / separation - 1.0) / e).clamp(-1.0, 1.0).acos()
+        } else {
+            0.0
+        };
+
+        let speed = (mu / semi_latus_rectum).sqrt();
+        let radial_speed = speed * e * anomaly.sin();
+        let tangential_speed = speed * (1.0 + e * anomaly.cos());
+
+        let direction = anomaly + self.angle.to_radians();
+        let (sin, cos) = direction.sin_cos();
+        (
+            [separation * cos, separation * sin],
+            [radial_speed * cos - tangential_speed * sin, radial_speed * sin + tangential_speed * cos],
+        )
+    }
+}
+
+/// Puts every system after the first on an orbit around all the ones before it, `orbits[i]` being the orbit of
+/// `systems[i + 1]`. The whole configuration ends up with its center of mass at the origin, at rest.
+/// Systems beyond the number of orbits are left where they are.
+pub fn place_on_orbits(systems: &mut [Vec<Body>], orbits: &[Orbit], gravitational_constant: f32) {
+    for (i, orbit) in orbits.iter().enumerate().take(systems.len().saturating_sub(1)) {
+        let (primary, rest) = systems.split_at_mut(i + 1);
+        let secondary = &mut rest[0];
+
+        let primary_state = center_of_mass(primary.iter().flatten());
+        let secondary_state = center_of_mass(secondary.iter());
+        let total_mass = primary_state.mass + secondary_state.mass;
+        if total_mass <= 0.0 {
+            continue;
+        }
+
+        let (position, velocity) = orbit.relative_state(gravitational_constant * total_mass);
+        let primary_share = secondary_state.mass / total_mass;
+        let secondary_share = primary_state.mass / total_mass;
+
+        let shift = |bodies: &mut dyn Iterator<Item = &mut Body>, from: &CenterOfMass, share: f32| {
+            for body in bodies {
+                for axis in 0..2 {
+                    body.position[axis] += position[axis] * share - from.position[axis];
+                    body.velocity[axis] += velocity[axis] * share - from.velocity[axis];
+                }
+            }
+        };
+        shift(&mut primary.iter_mut().flatten(), &primary_state, -primary_share);
+        shift(&mut secondary.iter_mut(), &secondary_state, secondary_share);
+    }
+}
+
+/// Turns a disk lying in the simulation plane the way it would look from above once tilted around the x axis by
+/// `inclination` degrees. With `retrograde` the disk is flipped over first, reversing its spin.
+pub fn incline(bodies: &mut [Body], inclination: f32, retrograde: bool) {
+    let flip = if retrograde { -1.0 } else { 1.0 };
+    let cos = inclination.to_radians().cos();
+    let center = center_of_mass(bodies.iter());
+
+    for body in bodies {
+        body.position[1] = center.position[1] + (body.position[1] - center.position[1]) * flip * cos;
+        body.velocity[1] = center.velocity[1] + (body.velocity[1] - center.velocity[1]) * flip * cos;
+    }
+}
+
+struct CenterOfMass {
+    mass: f32,
+    position: [f32; 2],
+    velocity: [f32; 2],
+}
+
+fn center_of_mass<'a>(bodies: impl Iterator<Item = &'a Body>) -> CenterOfMass {
+    let mut mass = 0.0f64;
+    let mut position = [0.0f64; 2];
+    let mut velocity = [0.0f64; 2];
+    for body in bodies {
+        let m = body.mass as f64;
+        mass += m;
+        for axis in 0..2 {
+            position[axis] += m * body.position[axis] as f64;
+            velocity[axis] += m * body.velocity[axis] as f64;
+        }
+    }
+
+    let scale = if mass > 0.0 { 1.0 / mass } else { 0.0 };
+    CenterOfMass {
+        mass: mass as f32,
+        position: position.map(|p| (p * scale) as f32),
+        velocity: velocity.map(|v| (v * scale) as f32),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //pericenter and eccentricity of the Kepler orbit through a relative position and velocity
+    fn elements(position: [f32; 2], velocity: [f32; 2], mu: f32) -> (f32, f32) {
+        let [x, y] = position.map(|p| p as f64);
+        let [vx, vy] = velocity.map(|v| v as f64);
+        let mu = mu as f64;
+        let angular_momentum = x * vy - y * vx;
+        let energy = 0.5 * (vx * vx + vy * vy) - mu / (x * x + y * y).sqrt();
+        let eccentricity = (1.0 + 2.0 * energy * angular_momentum * angular_momentum / (mu * mu)).max(0.0).sqrt();
+        let pericenter = angular_momentum * angular_momentum / (mu * (1.0 + eccentricity));
+        (pericenter as f32, eccentricity as f32)
+    }
+
+    #[test]
+    fn systems_approach_their_pericenter() {
+        let gravitational_constant = 2.0;
+        for (eccentricity, separation) in [(0.0, 10.0), (0.5, 20.0), (0.5, 1000.0), (1.0, 50.0), (1.5, 50.0)] {
+            let orbit = Orbit { pericenter: 5.0, eccentricity, separation, angle: 40.0 };
+            let mut systems = vec![
+                vec![Body::new(3.0, [1.0, 0.0], [0.0, 0.5]), Body::new(1.0, [-1.0, 0.0], [0.0, -1.5])],
+                vec![Body::new(2.0, [7.0, 7.0], [1.0, 1.0])],
+            ];
+            place_on_orbits(&mut systems, std::slice::from_ref(&orbit), gravitational_constant);
+
+            let all = center_of_mass(systems.iter().flatten());
+            assert!(all.position.iter().chain(&all.velocity).all(|v| v.abs() < 1e-4), "{eccentricity}: not at rest at the origin");
+
+            let primary = center_of_mass(systems[0].iter());
+            let secondary = center_of_mass(systems[1].iter());
+            let position = [0, 1].map(|axis| secondary.position[axis] - primary.position[axis]);
+            let velocity = [0, 1].map(|axis| secondary.velocity[axis] - primary.velocity[axis]);
+            let (pericenter, e) = elements(position, velocity, gravitational_constant * all.mass);
+            assert!((pericenter - orbit.pericenter).abs() < 1e-3 * orbit.pericenter, "{eccentricity}: pericenter {pericenter}");
+            assert!((e - eccentricity).abs() < 1e-3, "{eccentricity}: eccentricity {e}");
+
+            //still on the way in, unless the orbit is circular and always at its pericenter
+            let approaching = position[0] * velocity[0] + position[1] * velocity[1];
+            assert!(approaching <= 1e-3, "{eccentricity}: moving apart");
+        }
+    }
+}
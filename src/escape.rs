@@ -0,0 +1,218 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, Buffer, BufferAddress, CommandEncoder, ComputePipeline, ComputePipelineDescriptor, Device, include_wgsl, PipelineLayoutDescriptor, Queue};
+
+use crate::sim::{read_buffer, storage_layout_entry, uniform_layout_entry, BodyBuffers};
+
+//the escaper list starts with an atomic counter, padded so the records stay 8-byte aligned
+pub(crate) const ESCAPER_LIST_HEADER: BufferAddress = 8;
+pub(crate) const ESCAPER_SIZE: BufferAddress = std::mem::size_of::<Escaper>() as BufferAddress;
+
+//gpu record of a body that left the system, matches `Escaper` in escape_shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Escaper {
+    pub id: u32,
+    //specific energy relative to the system at the moment of escape
+    pub energy: f32,
+    pub mass: f32,
+    pub _padding: u32,
+    pub position: [f32; 2],
+    pub velocity: [f32; 2],
+}
+
+#[derive(Clone, Debug)]
+pub struct EscapeSettings {
+    //distance from the system's center of mass a body has to be past to count as escaped
+    pub radius: f32,
+    //how many ticks between two escaper checks
+    pub interval: u32,
+    //if set, every escaper gets appended to this file along with its exit time and velocity
+    pub log_path: Option<PathBuf>,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct EscapeStats {
+    pub escaped: u32,
+    pub escaped_mass: f32,
+}
+
+pub struct EscapeDetector {
+    pub pipeline: ComputePipeline,
+    pub bind_group_layout: BindGroupLayout,
+    pub bind_group: BindGroup,
+
+    pub settings: Option<EscapeSettings>,
+    pub log: Option<BufWriter<File>>,
+    pub stats: EscapeStats,
+}
+
+impl EscapeDetector {
+    pub fn new(device: &Device, buffers: &BodyBuffers, params_buffer: &Buffer) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Escape bind group layout"),
+            entries: &[
+                //params
+                uniform_layout_entry(0),
+                //positions
+                storage_layout_entry(1, true),
+                //velocities
+                storage_layout_entry(2, true),
+                //masses
+                storage_layout_entry(3, true),
+                //ids
+                storage_layout_entry(4, true),
+                //potentials
+                storage_layout_entry(5, true),
+                //flags
+                storage_layout_entry(6, false),
+                //escaper list
+                storage_layout_entry(7, false),
+                //system ranges
+                storage_layout_entry(8, true),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Escape pipeline layout"),
+            bind_group_layouts: &[
+                &bind_group_layout
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let shader = device.create_shader_module(include_wgsl!("escape_shader.wgsl"));
+
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Escape compute pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+
+        let bind_group = create_bind_group(device, &bind_group_layout, buffers, params_buffer);
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            bind_group,
+
+            settings: None,
+            log: None,
+            stats: EscapeStats::default(),
+        }
+    }
+
+    /// Opens the log, and its directory if needed, when the settings have one.
+    pub fn set_settings(&mut self, settings: Option<EscapeSettings>) -> std::io::Result<()> {
+        self.log = match settings.as_ref().and_then(|s| s.log_path.as_ref()) {
+            Some(path) => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let mut log = BufWriter::new(File::create(path)?);
+                writeln!(log, "time,id,mass,energy,x,y,vx,vy")?;
+                Some(log)
+            }
+            None => None,
+        };
+        self.settings = settings;
+        Ok(())
+    }
+
+    //has to be called whenever the body buffers get reallocated
+    pub fn rebind(&mut self, device: &Device, buffers: &BodyBuffers, params_buffer: &Buffer) {
+        self.bind_group = create_bind_group(device, &self.bind_group_layout, buffers, params_buffer);
+    }
+
+    pub fn is_due(&self, tick_count: u64) -> bool {
+        self.settings.as_ref().is_some_and(|s| tick_count.is_multiple_of(s.interval.max(1) as u64))
+    }
+
+    pub fn encode(&self, encoder: &mut CommandEncoder, buffers: &BodyBuffers, num_systems: u32) {
+        encoder.clear_buffer(&buffers.escapers_buffer, 0, Some(ESCAPER_LIST_HEADER));
+
+        let mut compute_pass = encoder.begin_compute_pass(&Default::default());
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.set_pipeline(&self.pipeline);
+        //one workgroup per ensemble member
+        compute_pass.dispatch_workgroups(num_systems, 1, 1);
+    }
+
+    /// Reads back the escapers flagged by the last pass, records them and returns how many there were. Fails when
+    /// the log can't be written to, the escapers are counted and stay flagged for removal either way.
+    pub async fn collect(&mut self, device: &Device, queue: &Queue, buffers: &BodyBuffers, time: f32) -> std::io::Result<u32> {
+        let header = read_buffer::<u32>(device, queue, &buffers.escapers_buffer, ESCAPER_LIST_HEADER).await;
+        let count = header[0];
+        if count == 0 {
+            return Ok(0);
+        }
+
+        let records = read_buffer::<u8>(device, queue, &buffers.escapers_buffer, ESCAPER_LIST_HEADER + count as BufferAddress * ESCAPER_SIZE).await;
+        let mut escapers = bytemuck::pod_collect_to_vec::<u8, Escaper>(&records[ESCAPER_LIST_HEADER as usize..]);
+        //records are appended atomically, so their order depends on scheduling
+        escapers.sort_by_key(|e| e.id);
+
+        for escaper in &escapers {
+            self.stats.escaped += 1;
+            self.stats.escaped_mass += escaper.mass;
+        }
+
+        if let Some(log) = &mut self.log {
+            for escaper in &escapers {
+                writeln!(log, "{},{},{},{},{},{},{},{}", time, escaper.id, escaper.mass, escaper.energy,
+                         escaper.position[0], escaper.position[1], escaper.velocity[0], escaper.velocity[1])?;
+            }
+            log.flush()?;
+        }
+
+        Ok(count)
+    }
+}
+
+fn create_bind_group(device: &Device, layout: &BindGroupLayout, buffers: &BodyBuffers, params_buffer: &Buffer) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Escape Bind Group"),
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: params_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: buffers.positions_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: buffers.velocities_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: buffers.input_masses.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 4,
+                resource: buffers.ids_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 5,
+                resource: buffers.potentials_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 6,
+                resource: buffers.flags_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 7,
+                resource: buffers.escapers_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 8,
+                resource: buffers.ranges_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
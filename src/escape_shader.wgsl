@@ -0,0 +1,91 @@
+//flags bodies that have escaped the system.
+//a body escapes once its energy relative to the system's center of mass is positive and it is further
+//than params.escape_radius from it. escaped bodies get their flag cleared (so the next compaction drops them)
+//and are appended to the escaper list so the cpu can log them.
+//runs one workgroup per ensemble member, since it first needs the center of mass of the whole member.
+
+struct SimParams {
+    num_bodies: u32,
+    dt: f32,
+    escape_radius: f32,
+}
+
+struct Escaper {
+    id: u32,
+    energy: f32,
+    mass: f32,
+    position: vec2<f32>,
+    velocity: vec2<f32>,
+}
+
+struct EscaperList {
+    count: atomic<u32>,
+    records: array<Escaper>,
+}
+
+@group(0) @binding(0)
+var<uniform> params: SimParams;
+@group(0) @binding(1)
+var<storage, read> positions: array<vec2<f32>>;
+@group(0) @binding(2)
+var<storage, read> velocities: array<vec2<f32>>;
+@group(0) @binding(3)
+var<storage, read> masses: array<f32>;
+@group(0) @binding(4)
+var<storage, read> ids: array<u32>;
+@group(0) @binding(5)
+var<storage, read> potentials: array<f32>;
+@group(0) @binding(6)
+var<storage, read_write> flags: array<u32>;
+@group(0) @binding(7)
+var<storage, read_write> escapers: EscaperList;
+@group(0) @binding(8)
+var<storage, read> ranges: array<vec2<u32>>;
+
+const WORKGROUP_SIZE = 256u;
+
+//x: mass, yz: mass weighted position, w unused
+var<workgroup> mass_sums: array<vec4<f32>, WORKGROUP_SIZE>;
+var<workgroup> momentum_sums: array<vec2<f32>, WORKGROUP_SIZE>;
+
+@compute
+@workgroup_size(256, 1, 1)
+fn main(@builtin(local_invocation_index) t: u32, @builtin(workgroup_id) workgroup_id: vec3<u32>) {
+    let range = ranges[workgroup_id.x];
+
+    var mass_sum = vec4<f32>(0.0);
+    var momentum_sum = vec2<f32>(0.0);
+    for (var i = range.x + t; i < range.y; i += WORKGROUP_SIZE) {
+        let m = masses[i];
+        mass_sum += vec4<f32>(m, positions[i] * m, 0.0);
+        momentum_sum += velocities[i] * m;
+    }
+    mass_sums[t] = mass_sum;
+    momentum_sums[t] = momentum_sum;
+    workgroupBarrier();
+
+    for (var stride = WORKGROUP_SIZE / 2u; stride > 0u; stride /= 2u) {
+        if (t < stride) {
+            mass_sums[t] += mass_sums[t + stride];
+            momentum_sums[t] += momentum_sums[t + stride];
+        }
+        workgroupBarrier();
+    }
+
+    //empty members have nothing to flag
+    let total_mass = max(mass_sums[0].x, 1e-30);
+    let center = mass_sums[0].yz / total_mass;
+    let center_velocity = momentum_sums[0] / total_mass;
+
+    for (var i = range.x + t; i < range.y; i += WORKGROUP_SIZE) {
+        let offset = positions[i] - center;
+        let velocity = velocities[i] - center_velocity;
+        let energy = 0.5 * dot(velocity, velocity) + potentials[i];
+
+        if (energy > 0.0 && dot(offset, offset) > params.escape_radius * params.escape_radius) {
+            flags[i] = 0u;
+            let index = atomicAdd(&escapers.count, 1u);
+            escapers.records[index] = Escaper(ids[i], energy, masses[i], positions[i], velocities[i]);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//pieces shared by the file format readers and writers.
+
+use std::io::{Error, ErrorKind, Read};
+
+/// Sequential reads of numbers out of a buffer in either byte order, the caller checks the size first.
+pub(crate) struct Fields<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    big_endian: bool,
+}
+
+impl<'a> Fields<'a> {
+    pub(crate) fn new(bytes: &'a [u8], big_endian: bool) -> Self {
+        Self { bytes, offset: 0, big_endian }
+    }
+
+    pub(crate) fn skip(&mut self, bytes: usize) {
+        self.offset += bytes;
+    }
+
+    pub(crate) fn seek(&mut self, offset: usize) {
+        self.offset = offset;
+    }
+
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let mut bytes: [u8; N] = self.bytes[self.offset..self.offset + N].try_into().unwrap();
+        self.offset += N;
+        if self.big_endian {
+            bytes.reverse();
+        }
+        bytes
+    }
+
+    pub(crate) fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.take())
+    }
+
+    pub(crate) fn i32(&mut self) -> i32 {
+        i32::from_le_bytes(self.take())
+    }
+
+    pub(crate) fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.take())
+    }
+
+    pub(crate) fn f32(&mut self) -> f32 {
+        f32::from_le_bytes(self.take())
+    }
+
+    pub(crate) fn f64(&mut self) -> f64 {
+        f64::from_le_bytes(self.take())
+    }
+}
+
+/// Reads `len` bytes, allocating as they arrive, so a damaged length can't ask for more memory than the file holds.
+pub(crate) fn read_bytes(reader: &mut impl Read, len: u64) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.by_ref().take(len).read_to_end(&mut data)?;
+    if (data.len() as u64) < len {
+        return Err(Error::new(ErrorKind::UnexpectedEof, format!("expected {len} more bytes, the file ends after {}", data.len())));
+    }
+    Ok(data)
+}
+
+/// A file that doesn't hold what its format says it should.
+pub(crate) fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Settings that can't be used.
+pub(crate) fn invalid_input(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
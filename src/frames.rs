@@ -0,0 +1,289 @@
+//a file per rendered frame: the image format, where the files go and what they are called, and an optional JSON
+//sidecar next to every image with what was on it.
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{ColorType, DynamicImage, ImageBuffer, ImageEncoder, Rgba};
+use serde::{Deserialize, Serialize};
+
+use crate::file_io::{invalid, invalid_input};
+use crate::units::UnitSystem;
+
+/// What is known about a frame when it gets written, for its file name and its sidecar.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FrameInfo {
+    pub frame: u32,
+    pub time: f32,
+    pub units: UnitSystem,
+    pub tick_count: u64,
+    pub num_bodies: u32,
+    pub camera_eye: [f32; 3],
+    pub camera_target: [f32; 3],
+    pub fovy: f32,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    #[default]
+    Jpeg,
+    Png,
+    //the 8 bit frames widened, for tools that want 16 bit input
+    Png16,
+    //lossless
+    Webp,
+    //linear float RGBA
+    Exr,
+}
+
+impl ImageFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpeg",
+            ImageFormat::Png | ImageFormat::Png16 => "png",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Exr => "exr",
+        }
+    }
+}
+
+/// How the frames are saved when every frame gets its own file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageOutput {
+    pub format: ImageFormat,
+    //1 to 100, jpeg only
+    pub quality: u8,
+    //created when the first frame is written
+    pub directory: PathBuf,
+    //file name without the extension. {frame} is the frame number, {frame:5} pads it with zeros to 5 digits,
+    //{time} is the simulation time with 3 decimals, {time:6} with 6, and {run} is the run name
+    pub name: String,
+    pub run: String,
+    //also write <name>.json with the frame's metadata
+    pub sidecar: bool,
+}
+
+impl Default for ImageOutput {
+    fn default() -> Self {
+        Self {
+            format: ImageFormat::Jpeg,
+            quality: 75,
+            directory: "output".into(),
+            name: "{frame:5}".into(),
+            run: "run".into(),
+            sidecar: false,
+        }
+    }
+}
+
+impl ImageOutput {
+    /// Checks the settings, so a bad name template shows up before the first frame does.
+    pub fn validate(&self) -> std::io::Result<()> {
+        if !(1..=100).contains(&self.quality) {
+            return Err(invalid_input(&format!("jpeg quality has to be between 1 and 100, not {}", self.quality)));
+        }
+        self.file_stem(&FrameInfo::default()).map(|_| ())
+    }
+
+    /// Where the image of a frame goes.
+    pub fn path(&self, info: &FrameInfo) -> std::io::Result<PathBuf> {
+        let stem = self.file_stem(info)?;
+        Ok(self.directory.join(format!("{stem}.{}", self.format.extension())))
+    }
+
+    /// Saves a frame, given as tightly packed RGBA rows from the top, and its sidecar.
+    pub fn save(&self, sequence: u64, info: &FrameInfo, width: u32, height: u32, rgba: Vec<u8>) -> std::io::Result<()> {
+        let path = self.path(info)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut writer = BufWriter::new(File::create(&path)?);
+        match self.format {
+            ImageFormat::Jpeg => JpegEncoder::new_with_quality(&mut writer, self.quality)
+                .write_image(&rgba, width, height, ColorType::Rgba8),
+            ImageFormat::Webp => WebPEncoder::new_lossless(&mut writer)
+                .write_image(&rgba, width, height, ColorType::Rgba8),
+            format => {
+                let image = DynamicImage::ImageRgba8(ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, rgba).unwrap());
+                let image = match format {
+                    ImageFormat::Png16 => DynamicImage::ImageRgba16(image.into_rgba16()),
+                    ImageFormat::Exr => {
+                        let mut image = image.into_rgba32f();
+                        //the frames are sRGB, EXR is linear
+                        for pixel in image.pixels_mut() {
+                            for channel in &mut pixel.0[..3] {
+                                *channel = srgb_to_linear(*channel);
+                            }
+                        }
+                        DynamicImage::ImageRgba32F(image)
+                    }
+                    _ => image,
+                };
+                let format = match self.format {
+                    ImageFormat::Exr => image::ImageFormat::OpenExr,
+                    _ => image::ImageFormat::Png,
+                };
+                image.write_to(&mut writer, format)
+            }
+        }.map_err(std::io::Error::other)?;
+        writer.flush()?;
+
+        if self.sidecar {
+            std::fs::write(path.with_extension("json"), self.sidecar_json(sequence, info, &path, width, height)?)?;
+        }
+        Ok(())
+    }
+
+    fn file_stem(&self, info: &FrameInfo) -> std::io::Result<String> {
+        let mut stem = String::new();
+        let mut rest = self.name.as_str();
+        while let Some(start) = rest.find('{') {
+            stem.push_str(&rest[..start]);
+            let end = rest[start..].find('}')
+                .ok_or_else(|| invalid_input(&format!("unclosed placeholder in {}", self.name)))? + start;
+            let (key, spec) = match rest[start + 1..end].split_once(':') {
+                Some((key, spec)) => {
+                    let spec = spec.parse::<usize>()
+                        .map_err(|_| invalid_input(&format!("{spec} in {} is not a number", self.name)))?;
+                    (key, Some(spec))
+                }
+                None => (&rest[start + 1..end], None),
+            };
+            match key {
+                "frame" => write!(stem, "{:0>1$}", info.frame, spec.unwrap_or(0)).unwrap(),
+                "time" => write!(stem, "{:.1$}", info.time, spec.unwrap_or(3)).unwrap(),
+                "run" => stem.push_str(&self.run),
+                _ => return Err(invalid_input(&format!("unknown placeholder {{{key}}} in {}, the placeholders are {{frame}}, {{time}} and {{run}}", self.name))),
+            }
+            rest = &rest[end + 1..];
+        }
+        stem.push_str(rest);
+        if stem.is_empty() {
+            return Err(invalid_input("the frame file name is empty"));
+        }
+        Ok(stem)
+    }
+
+    //JSON has no numbers for NaN and infinity, so metadata holding any of those is an error rather than a broken sidecar
+    fn sidecar_json(&self, sequence: u64, info: &FrameInfo, path: &Path, width: u32, height: u32) -> std::io::Result<String> {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let time_unit = match info.units {
+            UnitSystem::Code => "code",
+            units => units.time_name().trim(),
+        };
+        let number = |name: &str, value: f32| if value.is_finite() {
+            Ok(value.to_string())
+        } else {
+            Err(invalid(&format!("the {name} of frame {} is {value}, which can't go into its sidecar", info.frame)))
+        };
+        let vector = |name: &str, v: [f32; 3]| -> std::io::Result<String> {
+            Ok(format!("[{}, {}, {}]", number(name, v[0])?, number(name, v[1])?, number(name, v[2])?))
+        };
+        let time = number("time", info.time)?;
+        let eye = vector("camera position", info.camera_eye)?;
+        let target = vector("camera target", info.camera_target)?;
+        let fovy = number("field of view", info.fovy)?;
+
+        let mut json = String::new();
+        writeln!(json, "{{").unwrap();
+        writeln!(json, r#"  "file": {},"#, json_string(&file_name)).unwrap();
+        writeln!(json, r#"  "run": {},"#, json_string(&self.run)).unwrap();
+        writeln!(json, r#"  "frame": {},"#, info.frame).unwrap();
+        writeln!(json, r#"  "sequence": {sequence},"#).unwrap();
+        writeln!(json, r#"  "tick": {},"#, info.tick_count).unwrap();
+        writeln!(json, r#"  "time": {time},"#).unwrap();
+        writeln!(json, r#"  "time_unit": {},"#, json_string(time_unit)).unwrap();
+        writeln!(json, r#"  "bodies": {},"#, info.num_bodies).unwrap();
+        writeln!(json, r#"  "width": {width},"#).unwrap();
+        writeln!(json, r#"  "height": {height},"#).unwrap();
+        writeln!(json, r#"  "format": {},"#, json_string(&format!("{:?}", self.format).to_lowercase())).unwrap();
+        writeln!(json, r#"  "camera": {{"eye": {eye}, "target": {target}, "fovy": {fovy}}}"#).unwrap();
+        writeln!(json, "}}").unwrap();
+        Ok(json)
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(name: &str) -> ImageOutput {
+        ImageOutput { name: name.into(), run: "merger".into(), ..Default::default() }
+    }
+
+    #[test]
+    fn file_names_fill_in_the_placeholders() {
+        let info = FrameInfo { frame: 42, time: 1.234567, ..Default::default() };
+        let stem = |name: &str| output(name).file_stem(&info).unwrap();
+        assert_eq!(stem("{frame}"), "42");
+        assert_eq!(stem("{frame:5}"), "00042");
+        assert_eq!(stem("{frame:1}"), "42");
+        assert_eq!(stem("{time}"), "1.235");
+        assert_eq!(stem("{time:6}"), "1.234567");
+        assert_eq!(stem("{run}_{frame:3}_t{time:1}"), "merger_042_t1.2");
+        assert_eq!(stem("plain"), "plain");
+        assert_eq!(output("{run}/{frame:4}").path(&info).unwrap(), Path::new("output/merger/0042.jpeg"));
+    }
+
+    #[test]
+    fn bad_settings_are_rejected() {
+        for name in ["{frames}", "{frame", "{frame:x}", "", "{}"] {
+            let error = output(name).validate().unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput, "{name}");
+        }
+        assert!(output("{frame:5}").validate().is_ok());
+
+        for quality in [0, 101] {
+            assert!(ImageOutput { quality, ..Default::default() }.validate().is_err(), "{quality}");
+        }
+    }
+
+    #[test]
+    fn sidecars_hold_only_json_numbers() {
+        let output = ImageOutput { format: ImageFormat::Png, sidecar: true, ..output("a \"quoted\" name") };
+        let info = FrameInfo { frame: 7, time: 0.5, fovy: 45.0, camera_eye: [0.0, 0.0, 100.0], ..Default::default() };
+        let path = output.path(&info).unwrap();
+        let json = output.sidecar_json(3, &info, &path, 640, 480).unwrap();
+        assert!(json.contains(r#""file": "a \"quoted\" name.png","#), "{json}");
+        assert!(json.contains(r#""time": 0.5,"#), "{json}");
+        assert!(json.contains(r#""camera": {"eye": [0, 0, 100], "target": [0, 0, 0], "fovy": 45}"#), "{json}");
+
+        for info in [
+            FrameInfo { time: f32::NAN, ..info },
+            FrameInfo { fovy: f32::INFINITY, ..info },
+            FrameInfo { camera_eye: [0.0, f32::NEG_INFINITY, 1.0], ..info },
+        ] {
+            let error = output.sidecar_json(3, &info, &path, 640, 480).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,425 @@
+//Gadget-2 snapshots, in the plain (format 1) and the labelled block (format 2) flavours, so initial conditions made
+//with the usual astrophysics tools can be run here and our runs can be looked at with them.
+//bodies are written with z = 0 and read seen from above, values are in the simulation's units as they are.
+//the six Gadget particle types (gas, halo, disk, bulge, stars, boundary) map to body species 1 to 6. untagged bodies
+//(species 0) are written as halo particles, generated disk galaxies come tagged with their parts.
+//only the header, positions, velocities, ids and masses are read, other blocks are skipped. gas gets a zero internal
+//energy block on write, Gadget expects one in initial conditions.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+use wgpu::{Device, Queue};
+
+use crate::file_io::{invalid, read_bytes, Fields};
+use crate::sim::{Body, SimState};
+
+pub const NUM_PARTICLE_TYPES: usize = 6;
+//body species of the Gadget particle types
+pub const GAS_SPECIES: u32 = 1;
+pub const HALO_SPECIES: u32 = 2;
+pub const DISK_SPECIES: u32 = 3;
+pub const BULGE_SPECIES: u32 = 4;
+pub const STAR_SPECIES: u32 = 5;
+//usually black holes
+pub const BOUNDARY_SPECIES: u32 = 6;
+const HEADER_SIZE: usize = 256;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum GadgetFormat {
+    //fortran records one after the other, in a fixed order
+    #[default]
+    Format1,
+    //every block preceded by a small record with its 4 character name
+    Format2,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GadgetSnapshot {
+    //sorted by particle type
+    pub bodies: Vec<Body>,
+    pub ids: Vec<u64>,
+    //scale factor in cosmological runs
+    pub time: f64,
+    pub redshift: f64,
+    pub box_size: f64,
+    pub omega0: f64,
+    pub omega_lambda: f64,
+    pub hubble_param: f64,
+}
+
+/// Gadget particle type of a body species. Untagged bodies are halo particles, so they come back as `HALO_SPECIES`.
+pub fn particle_type(species: u32) -> usize {
+    match species {
+        0 => 1,
+        s => (s as usize - 1).min(NUM_PARTICLE_TYPES - 1),
+    }
+}
+
+/// Body species of a Gadget particle type.
+pub fn species(particle_type: usize) -> u32 {
+    particle_type as u32 + 1
+}
+
+impl GadgetSnapshot {
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>, format: GadgetFormat) -> std::io::Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer, format)?;
+        writer.flush()
+    }
+
+    /// Reads either format in either byte order, with single or double precision and 32 or 64 bit ids.
+    pub fn read(reader: &mut impl Read) -> std::io::Result<Self> {
+        let mut records = RecordReader::new(reader)?;
+
+        let header = records.block(b"HEAD")?.ok_or_else(|| invalid("missing header"))?;
+        if header.len() < HEADER_SIZE {
+            return Err(invalid("header too short"));
+        }
+        let mut fields = Fields::new(&header, records.big_endian);
+        let counts = [(); NUM_PARTICLE_TYPES].map(|_| fields.u32() as usize);
+        let type_masses = [(); NUM_PARTICLE_TYPES].map(|_| fields.f64());
+        let time = fields.f64();
+        let redshift = fields.f64();
+        //star formation and feedback flags, total counts, cooling flag
+        fields.skip(4 * 2 + 4 * NUM_PARTICLE_TYPES + 4);
+        if fields.i32() > 1 {
+            return Err(invalid("snapshots split over several files are not supported"));
+        }
+        let box_size = fields.f64();
+        let omega0 = fields.f64();
+        let omega_lambda = fields.f64();
+        let hubble_param = fields.f64();
+
+        let num_bodies: usize = counts.iter().sum();
+        let num_variable_masses: usize = counts.iter().zip(type_masses).filter(|(_, m)| *m == 0.0).map(|(c, _)| c).sum();
+
+        let positions = records.block(b"POS ")?.ok_or_else(|| invalid("missing positions"))?;
+        let positions = records.floats(&positions, num_bodies * 3)?;
+        let velocities = records.block(b"VEL ")?.ok_or_else(|| invalid("missing velocities"))?;
+        let velocities = records.floats(&velocities, num_bodies * 3)?;
+        let ids = records.block(b"ID  ")?.ok_or_else(|| invalid("missing ids"))?;
+        let ids = records.ids(&ids, num_bodies)?;
+        let masses = if num_variable_masses > 0 {
+            let masses = records.block(b"MASS")?.ok_or_else(|| invalid("missing masses"))?;
+            records.floats(&masses, num_variable_masses)?
+        } else {
+            Vec::new()
+        };
+
+        let mut bodies = Vec::with_capacity(num_bodies);
+        let mut variable_masses = masses.into_iter();
+        for (particle_type, count) in counts.into_iter().enumerate() {
+            for _ in 0..count {
+                let i = bodies.len();
+                let mass = if type_masses[particle_type] == 0.0 {
+                    variable_masses.next().unwrap_or(0.0)
+                } else {
+                    type_masses[particle_type] as f32
+                };
+                let position = [positions[i * 3], positions[i * 3 + 1]];
+                let velocity = [velocities[i * 3], velocities[i * 3 + 1]];
+                bodies.push(Body::new(mass, position, velocity).with_species(species(particle_type)));
+            }
+        }
+
+        Ok(Self {
+            bodies,
+            ids,
+            time,
+            redshift,
+            box_size,
+            omega0,
+            omega_lambda,
+            hubble_param,
+        })
+    }
+
+    /// Writes the bodies as a little endian, single precision snapshot with 32 bit ids, or 64 bit ones when an id
+    /// doesn't fit. Bodies are reordered by particle type, types whose bodies all weigh the same get their mass in the
+    /// header instead of the mass block.
+    pub fn write(&self, writer: &mut impl Write, format: GadgetFormat) -> std::io::Result<()> {
+        let mut order = (0..self.bodies.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| particle_type(self.bodies[i].species));
+
+        let mut counts = [0u32; NUM_PARTICLE_TYPES];
+        let mut type_masses = [None::<f32>; NUM_PARTICLE_TYPES];
+        let mut uniform = [true; NUM_PARTICLE_TYPES];
+        for body in &self.bodies {
+            let t = particle_type(body.species);
+            counts[t] += 1;
+            match type_masses[t] {
+                None => type_masses[t] = Some(body.mass),
+                Some(m) if m != body.mass => uniform[t] = false,
+                _ => {}
+            }
+        }
+        //a mass of 0 in the header means the masses are in the mass block
+        let header_masses = (0..NUM_PARTICLE_TYPES).map(|t| match type_masses[t] {
+            Some(m) if uniform[t] && m != 0.0 => m as f64,
+            _ => 0.0,
+        }).collect::<Vec<_>>();
+
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        for count in counts {
+            header.extend_from_slice(&count.to_le_bytes());
+        }
+        for mass in &header_masses {
+            header.extend_from_slice(&mass.to_le_bytes());
+        }
+        header.extend_from_slice(&self.time.to_le_bytes());
+        header.extend_from_slice(&self.redshift.to_le_bytes());
+        //no star formation, no feedback
+        header.extend_from_slice(&[0; 8]);
+        for count in counts {
+            header.extend_from_slice(&count.to_le_bytes());
+        }
+        //no cooling, a single file
+        header.extend_from_slice(&0i32.to_le_bytes());
+        header.extend_from_slice(&1i32.to_le_bytes());
+        for value in [self.box_size, self.omega0, self.omega_lambda, self.hubble_param] {
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+        header.resize(HEADER_SIZE, 0);
+        write_block(writer, format, b"HEAD", &header)?;
+
+        let vectors = |f: &dyn Fn(&Body) -> [f32; 2]| {
+            order.iter().flat_map(|&i| {
+                let [x, y] = f(&self.bodies[i]);
+                [x, y, 0.0]
+            }).flat_map(f32::to_le_bytes).collect::<Vec<_>>()
+        };
+        write_block(writer, format, b"POS ", &vectors(&|b| b.position))?;
+        write_block(writer, format, b"VEL ", &vectors(&|b| b.velocity))?;
+
+        let ids = order.iter().map(|&i| self.ids.get(i).copied().unwrap_or(i as u64)).collect::<Vec<_>>();
+        let ids = if ids.iter().all(|id| *id <= u32::MAX as u64) {
+            ids.into_iter().flat_map(|id| (id as u32).to_le_bytes()).collect::<Vec<_>>()
+        } else {
+            ids.into_iter().flat_map(u64::to_le_bytes).collect()
+        };
+        write_block(writer, format, b"ID  ", &ids)?;
+
+        let masses = order.iter().map(|&i| &self.bodies[i]).filter(|b| header_masses[particle_type(b.species)] == 0.0)
+            .flat_map(|b| b.mass.to_le_bytes()).collect::<Vec<_>>();
+        if !masses.is_empty() {
+            write_block(writer, format, b"MASS", &masses)?;
+        }
+
+        if counts[0] > 0 {
+            write_block(writer, format, b"U   ", &vec![0; counts[0] as usize * 4])?;
+        }
+        Ok(())
+    }
+}
+
+impl SimState {
+    /// Starts a simulation from a Gadget snapshot, all bodies in one system.
+    pub fn from_gadget(device: &Device, queue: &Queue, snapshot: &GadgetSnapshot) -> Self {
+        let mut sim_state = Self::empty(device, snapshot.bodies.len() as u32);
+        sim_state.add_system(device, queue, &snapshot.bodies);
+        sim_state.time = snapshot.time as f32;
+        sim_state
+    }
+
+    /// The live bodies of every system as a Gadget snapshot, with the body ids as particle ids.
+    pub async fn to_gadget(&self, device: &Device, queue: &Queue) -> GadgetSnapshot {
+        GadgetSnapshot {
+            bodies: self.read_bodies(device, queue).await,
+            ids: self.read_ids(device, queue).await.into_iter().map(u64::from).collect(),
+            time: self.time as f64,
+            ..Default::default()
+        }
+    }
+}
+
+fn write_block(writer: &mut impl Write, format: GadgetFormat, label: &[u8; 4], data: &[u8]) -> std::io::Result<()> {
+    if format == GadgetFormat::Format2 {
+        writer.write_all(&8u32.to_le_bytes())?;
+        writer.write_all(label)?;
+        writer.write_all(&(data.len() as u32 + 8).to_le_bytes())?;
+        writer.write_all(&8u32.to_le_bytes())?;
+    }
+    writer.write_all(&(data.len() as u32).to_le_bytes())?;
+    writer.write_all(data)?;
+    writer.write_all(&(data.len() as u32).to_le_bytes())
+}
+
+//fortran unformatted records, every one framed by its length in bytes on both sides
+struct RecordReader<'a, R: Read> {
+    reader: &'a mut R,
+    format: GadgetFormat,
+    big_endian: bool,
+    //first length marker, already read to find out the format and byte order
+    pending: Option<u32>,
+}
+
+impl<'a, R: Read> RecordReader<'a, R> {
+    fn new(reader: &'a mut R) -> std::io::Result<Self> {
+        let mut marker = [0; 4];
+        reader.read_exact(&mut marker)?;
+        let (format, big_endian) = match (u32::from_le_bytes(marker), u32::from_be_bytes(marker)) {
+            (256, _) => (GadgetFormat::Format1, false),
+            (_, 256) => (GadgetFormat::Format1, true),
+            (8, _) => (GadgetFormat::Format2, false),
+            (_, 8) => (GadgetFormat::Format2, true),
+            _ => return Err(invalid("not a Gadget snapshot")),
+        };
+        let pending = Some(if big_endian { u32::from_be_bytes(marker) } else { u32::from_le_bytes(marker) });
+        Ok(Self { reader, format, big_endian, pending })
+    }
+
+    //the next block, or the block with this label in format 2, None at the end of the file
+    fn block(&mut self, label: &[u8; 4]) -> std::io::Result<Option<Vec<u8>>> {
+        loop {
+            let Some(record) = self.record()? else {
+                return Ok(None);
+            };
+            if self.format == GadgetFormat::Format1 {
+                return Ok(Some(record));
+            }
+            if record.len() < 4 {
+                return Err(invalid("malformed block label"));
+            }
+            let data = self.record()?.ok_or_else(|| invalid("block label at the end of the file"))?;
+            if &record[..4] == label {
+                return Ok(Some(data));
+            }
+        }
+    }
+
+    fn record(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        let length = match self.pending.take() {
+            Some(length) => length,
+            None => {
+                let mut marker = [0; 4];
+                match self.reader.read_exact(&mut marker) {
+                    Ok(()) => self.u32(marker),
+                    Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                    Err(e) => return Err(e),
+                }
+            }
+        };
+        let data = read_bytes(self.reader, length as u64)?;
+        let mut marker = [0; 4];
+        self.reader.read_exact(&mut marker)?;
+        if self.u32(marker) != length {
+            return Err(invalid("record length markers don't match"));
+        }
+        Ok(Some(data))
+    }
+
+    fn u32(&self, bytes: [u8; 4]) -> u32 {
+        if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    }
+
+    //`count` floats, stored in single or double precision
+    fn floats(&self, data: &[u8], count: usize) -> std::io::Result<Vec<f32>> {
+        let mut fields = Fields::new(data, self.big_endian);
+        match data.len() {
+            n if n == count * 4 => Ok((0..count).map(|_| fields.f32()).collect()),
+            n if n == count * 8 => Ok((0..count).map(|_| fields.f64() as f32).collect()),
+            _ => Err(invalid("block size doesn't match the particle count")),
+        }
+    }
+
+    fn ids(&self, data: &[u8], count: usize) -> std::io::Result<Vec<u64>> {
+        let mut fields = Fields::new(data, self.big_endian);
+        match data.len() {
+            n if n == count * 4 => Ok((0..count).map(|_| fields.u32() as u64).collect()),
+            n if n == count * 8 => Ok((0..count).map(|_| fields.u64()).collect()),
+            _ => Err(invalid("id block size doesn't match the particle count")),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::galaxy::DiskGalaxy;
+
+    fn round_trip(format: GadgetFormat) {
+        let bodies = DiskGalaxy::default().generate(&mut SmallRng::seed_from_u64(0));
+        let snapshot = GadgetSnapshot {
+            ids: (0..bodies.len() as u64).collect(),
+            bodies,
+            ..Default::default()
+        };
+        let mut data = Vec::new();
+        snapshot.write(&mut data, format).unwrap();
+        let read = GadgetSnapshot::read(&mut data.as_slice()).unwrap();
+
+        //the writer sorts by particle type, keeping the order within a type
+        let mut order = (0..snapshot.bodies.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| particle_type(snapshot.bodies[i].species));
+        let expected = order.iter().map(|&i| snapshot.bodies[i]).collect::<Vec<_>>();
+        let expected_ids = order.iter().map(|&i| snapshot.ids[i]).collect::<Vec<_>>();
+
+        assert_eq!(read.ids, expected_ids);
+        assert_eq!(bytemuck::cast_slice::<Body, u8>(&read.bodies), bytemuck::cast_slice::<Body, u8>(&expected));
+        assert!(read.bodies.iter().any(|b| b.species == DISK_SPECIES));
+        assert!(read.bodies.iter().any(|b| b.species == BULGE_SPECIES));
+    }
+
+    #[test]
+    fn format_1_round_trip() {
+        round_trip(GadgetFormat::Format1);
+    }
+
+    #[test]
+    fn format_2_round_trip() {
+        round_trip(GadgetFormat::Format2);
+    }
+
+    #[test]
+    fn untagged_bodies_are_halo() {
+        let snapshot = GadgetSnapshot {
+            bodies: vec![Body::new(1.0, [1.0, 2.0], [3.0, 4.0]); 3],
+            ..Default::default()
+        };
+        let mut data = Vec::new();
+        snapshot.write(&mut data, GadgetFormat::Format1).unwrap();
+        let read = GadgetSnapshot::read(&mut data.as_slice()).unwrap();
+
+        assert_eq!(read.ids, [0, 1, 2]);
+        assert!(read.bodies.iter().all(|b| b.species == HALO_SPECIES && b.mass == 1.0 && b.position == [1.0, 2.0]));
+    }
+
+    #[test]
+    fn ids_past_32_bits_are_kept() {
+        let snapshot = GadgetSnapshot {
+            bodies: vec![Body::new(1.0, [1.0, 2.0], [3.0, 4.0]); 2],
+            ids: vec![7, u32::MAX as u64 + 5],
+            ..Default::default()
+        };
+        let mut data = Vec::new();
+        snapshot.write(&mut data, GadgetFormat::Format2).unwrap();
+        assert_eq!(GadgetSnapshot::read(&mut data.as_slice()).unwrap().ids, snapshot.ids);
+    }
+
+    #[test]
+    fn huge_record_lengths_fail_without_allocating() {
+        let snapshot = GadgetSnapshot {
+            bodies: vec![Body::new(1.0, [1.0, 2.0], [3.0, 4.0]); 2],
+            ..Default::default()
+        };
+        let mut data = Vec::new();
+        snapshot.write(&mut data, GadgetFormat::Format1).unwrap();
+        //the positions' leading length marker, right after the header record
+        let marker = 4 + HEADER_SIZE + 4;
+        data[marker..marker + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(GadgetSnapshot::read(&mut data.as_slice()).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+}
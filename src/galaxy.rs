@@ -0,0 +1,380 @@
+//disk galaxies in equilibrium: an exponential disk, a Hernquist bulge and an optional live NFW halo.
+//the simulation is planar, so rather than the spherical v^2 = GM(<r)/r the circular velocities come from the actual
+//in-plane pull of the sampled mass profile (summed ring by ring), and the dispersions from the planar Jeans equation.
+
+use std::f32::consts::PI;
+
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+use serde::{Deserialize, Serialize};
+
+use crate::gadget::{BOUNDARY_SPECIES, BULGE_SPECIES, DISK_SPECIES, HALO_SPECIES};
+use crate::imf::MassFunction;
+use crate::sim::Body;
+
+//disk particles further out than this many scale lengths get resampled
+const DISK_TRUNCATION: f32 = 10.0;
+//same for the bulge, in units of its scale radius
+const BULGE_TRUNCATION: f32 = 20.0;
+const PROFILE_RINGS: usize = 256;
+const RING_QUADRATURE: usize = 128;
+//stellar disks are stable against axisymmetric perturbations for Q > 1 with this constant
+const TOOMRE_CONSTANT: f32 = 3.36;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiskGalaxy {
+    pub disk_mass: f32,
+    pub disk_scale_length: f32,
+    pub disk_bodies: u32,
+
+    pub bulge_mass: f32,
+    pub bulge_scale_radius: f32,
+    pub bulge_bodies: u32,
+
+    pub halo: Option<NfwHalo>,
+
+    //optional central black hole, 0 for none
+    pub center_mass: f32,
+
+    //radial velocity dispersion of the disk is set so it has this Toomre Q everywhere
+    pub toomre_q: f32,
+    pub clockwise: bool,
+}
+
+impl Default for DiskGalaxy {
+    fn default() -> Self {
+        Self {
+            disk_mass: 40_000.0,
+            disk_scale_length: 8.0,
+            disk_bodies: 16_000,
+
+            bulge_mass: 10_000.0,
+            bulge_scale_radius: 2.0,
+            bulge_bodies: 4_000,
+
+            halo: None,
+
+            center_mass: 0.0,
+
+            toomre_q: 1.5,
+            clockwise: false,
+        }
+    }
+}
+
+/// A live halo, represented by its own particles.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NfwHalo {
+    //mass inside the truncation radius
+    pub mass: f32,
+    pub scale_radius: f32,
+    pub truncation_radius: f32,
+    pub num_bodies: u32,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Part {
+    Disk,
+    Bulge,
+    Halo,
+}
+
+impl DiskGalaxy {
+    /// Generates the galaxy centered on the origin, at rest.
+    pub fn generate(&self, rng: &mut impl Rng) -> Vec<Body> {
+        self.generate_with_mass_function(None, rng)
+    }
+
+    /// Like `generate`, but with the disk and bulge stars drawn from `mass_function`. Each keeps its share of the
+    /// stellar mass, the halo is left alone.
+    pub fn generate_with_mass_function(&self, mass_function: Option<&MassFunction>, rng: &mut impl Rng) -> Vec<Body> {
+        let mut positions: Vec<([f32; 2], f32, Part)> = Vec::new();
+
+        let disk_body_mass = self.disk_mass / self.disk_bodies.max(1) as f32;
+        for _ in 0..self.disk_bodies {
+            let r = sample_exponential_disk_radius(self.disk_scale_length, rng);
+            positions.push((random_on_circle(r, rng), disk_body_mass, Part::Disk));
+        }
+
+        let bulge_body_mass = self.bulge_mass / self.bulge_bodies.max(1) as f32;
+        for _ in 0..self.bulge_bodies {
+            let r = sample_hernquist_radius(self.bulge_scale_radius, BULGE_TRUNCATION * self.bulge_scale_radius, rng);
+            positions.push((project_from_sphere(r, rng), bulge_body_mass, Part::Bulge));
+        }
+
+        if let Some(halo) = &self.halo {
+            let halo_body_mass = halo.mass / halo.num_bodies.max(1) as f32;
+            for _ in 0..halo.num_bodies {
+                let r = sample_nfw_radius(halo.scale_radius, halo.truncation_radius, rng);
+                positions.push((project_from_sphere(r, rng), halo_body_mass, Part::Halo));
+            }
+        }
+
+        if let Some(mass_function) = mass_function {
+            let stellar_mass = self.disk_mass + self.bulge_mass;
+            let scale = mass_function.total_mass.map_or(1.0, |total| total / stellar_mass);
+            for (part, mass) in [(Part::Disk, self.disk_mass), (Part::Bulge, self.bulge_mass)] {
+                let count = positions.iter().filter(|(_, _, c)| *c == part).count();
+                let masses = mass_function.sample_total(count, mass * scale, rng);
+                for ((_, m, _), mass) in positions.iter_mut().filter(|(_, _, c)| *c == part).zip(masses) {
+                    *m = mass;
+                }
+            }
+        }
+
+        let max_radius = positions.iter().map(|(p, _, _)| length(*p)).fold(0.0f32, f32::max).max(1e-3);
+        let total = RadialProfile::from_samples(positions.iter().map(|(p, m, _)| (length(*p), *m)), max_radius);
+        let acceleration = total.radial_acceleration(self.center_mass);
+
+        let spheroid_dispersion = |part: Part| {
+            let profile = RadialProfile::from_samples(positions.iter().filter(|(_, _, c)| *c == part).map(|(p, m, _)| (length(*p), *m)), max_radius);
+            profile.jeans_dispersion(&acceleration)
+        };
+        let bulge_dispersion = spheroid_dispersion(Part::Bulge);
+        let halo_dispersion = spheroid_dispersion(Part::Halo);
+
+        let normal = Normal::new(0.0f32, 1.0).unwrap();
+        let mut bodies = Vec::with_capacity(positions.len() + 1);
+
+        if self.center_mass > 0.0 {
+            bodies.push(Body::new(self.center_mass, [0.0, 0.0], [0.0, 0.0]).with_species(BOUNDARY_SPECIES));
+        }
+
+        for (position, mass, part) in positions {
+            let r = length(position);
+            let ring = total.ring(r);
+
+            let velocity = match part {
+                Part::Disk => {
+                    let (v_radial, v_tangential) = self.disk_velocity(r, ring, &total, &acceleration, &normal, rng);
+                    let direction = if self.clockwise { -1.0 } else { 1.0 };
+                    let [cos, sin] = if r > 0.0 { [position[0] / r, position[1] / r] } else { [1.0, 0.0] };
+                    [
+                        v_radial * cos - direction * v_tangential * sin,
+                        v_radial * sin + direction * v_tangential * cos,
+                    ]
+                }
+                Part::Bulge | Part::Halo => {
+                    let sigma = if part == Part::Bulge { bulge_dispersion[ring] } else { halo_dispersion[ring] };
+                    [normal.sample(rng) * sigma, normal.sample(rng) * sigma]
+                }
+            };
+
+            let species = match part {
+                Part::Disk => DISK_SPECIES,
+                Part::Bulge => BULGE_SPECIES,
+                Part::Halo => HALO_SPECIES,
+            };
+            bodies.push(Body::new(mass, position, velocity).with_species(species));
+        }
+
+        bodies
+    }
+
+    //radial and tangential velocity of a disk body at radius r
+    fn disk_velocity(&self, r: f32, ring: usize, profile: &RadialProfile, acceleration: &[f32], normal: &Normal<f32>, rng: &mut impl Rng) -> (f32, f32) {
+        let h = self.disk_scale_length;
+        let r = r.max(profile.ring_width * 0.5);
+
+        let circular_velocity_sq = acceleration[ring] * r;
+        let omega_sq = acceleration[ring] / r;
+        let kappa_sq = profile.epicyclic_frequency_sq(acceleration, ring).max(1e-12);
+
+        let surface_density = self.disk_mass / (2.0 * PI * h * h) * (-r / h).exp();
+        let sigma_radial = self.toomre_q * TOOMRE_CONSTANT * surface_density / kappa_sq.sqrt();
+        let sigma_tangential = sigma_radial * (kappa_sq / (4.0 * omega_sq.max(1e-12))).sqrt();
+
+        //asymmetric drift, the mean rotation lags behind the circular velocity where the disk is hot
+        let mean_sq = circular_velocity_sq + sigma_radial * sigma_radial * (1.0 - kappa_sq / (4.0 * omega_sq.max(1e-12)) - 2.0 * r / h);
+        let mean_tangential = mean_sq.max(0.0).sqrt();
+
+        (
+            normal.sample(rng) * sigma_radial,
+            mean_tangential + normal.sample(rng) * sigma_tangential,
+        )
+    }
+}
+
+/// Mass binned into equally wide rings around the origin.
+pub(crate) struct RadialProfile {
+    pub ring_width: f32,
+    pub masses: Vec<f32>,
+}
+
+impl RadialProfile {
+    pub fn from_samples(samples: impl Iterator<Item = (f32, f32)>, max_radius: f32) -> Self {
+        let ring_width = max_radius / PROFILE_RINGS as f32;
+        let mut masses = vec![0.0; PROFILE_RINGS];
+        for (r, m) in samples {
+            masses[((r / ring_width) as usize).min(PROFILE_RINGS - 1)] += m;
+        }
+        Self { ring_width, masses }
+    }
+
+    pub fn ring(&self, r: f32) -> usize {
+        ((r / self.ring_width) as usize).min(self.masses.len() - 1)
+    }
+
+    fn radius(&self, ring: usize) -> f32 {
+        (ring as f32 + 0.5) * self.ring_width
+    }
+
+    fn surface_density(&self, ring: usize) -> f32 {
+        self.masses[ring] / (2.0 * PI * self.radius(ring) * self.ring_width)
+    }
+
+    /// Inward acceleration at the middle of every ring, from every ring plus a central point mass, all in the plane.
+    pub fn radial_acceleration(&self, center_mass: f32) -> Vec<f32> {
+        let d_phi = PI / RING_QUADRATURE as f32;
+        //keeps rings from blowing up right where they sit
+        let softening_sq = self.ring_width * self.ring_width;
+
+        (0..self.masses.len()).map(|i| {
+            let r = self.radius(i);
+            let mut acceleration = center_mass / (r * r);
+
+            for (j, mass) in self.masses.iter().enumerate() {
+                if *mass == 0.0 {
+                    continue;
+                }
+                let a = self.radius(j);
+                let mut ring_pull = 0.0;
+                for k in 0..RING_QUADRATURE {
+                    let phi = (k as f32 + 0.5) * d_phi;
+                    let distance_sq = r * r + a * a - 2.0 * a * r * phi.cos() + softening_sq;
+                    ring_pull += (r - a * phi.cos()) / (distance_sq * distance_sq.sqrt());
+                }
+                acceleration += mass * ring_pull * d_phi / PI;
+            }
+
+            acceleration
+        }).collect()
+    }
+
+    /// kappa^2 = R dOmega^2/dR + 4 Omega^2, with the derivative taken between neighbouring rings.
+    pub fn epicyclic_frequency_sq(&self, acceleration: &[f32], ring: usize) -> f32 {
+        let omega_sq = |i: usize| acceleration[i] / self.radius(i);
+        let lower = ring.saturating_sub(1);
+        let upper = (ring + 1).min(acceleration.len() - 1);
+        let derivative = (omega_sq(upper) - omega_sq(lower)) / (self.radius(upper) - self.radius(lower)).max(1e-12);
+        self.radius(ring) * derivative + 4.0 * omega_sq(ring)
+    }
+
+    /// Isotropic velocity dispersion of a non rotating planar component in the given potential,
+    /// sigma^2(R) = 1/Sigma(R) * integral from R to infinity of Sigma * acceleration.
+    pub fn jeans_dispersion(&self, acceleration: &[f32]) -> Vec<f32> {
+        let mut dispersion = vec![0.0; self.masses.len()];
+        let mut integral = 0.0;
+        for i in (0..self.masses.len()).rev() {
+            let surface_density = self.surface_density(i);
+            integral += surface_density * acceleration[i] * self.ring_width;
+            if surface_density > 0.0 {
+                dispersion[i] = (integral / surface_density).sqrt();
+            }
+        }
+        dispersion
+    }
+}
+
+//the cumulative mass of an exponential disk is a gamma(2) distribution, the sum of two exponentials
+fn sample_exponential_disk_radius(scale_length: f32, rng: &mut impl Rng) -> f32 {
+    loop {
+        let u1: f32 = rng.gen_range(f32::EPSILON..1.0);
+        let u2: f32 = rng.gen_range(f32::EPSILON..1.0);
+        let r = -scale_length * (u1 * u2).ln();
+        if r < DISK_TRUNCATION * scale_length {
+            return r;
+        }
+    }
+}
+
+//inverse of the Hernquist cumulative mass M(<r) / M = r^2 / (r + a)^2
+pub(crate) fn sample_hernquist_radius(scale_radius: f32, truncation: f32, rng: &mut impl Rng) -> f32 {
+    loop {
+        let u: f32 = rng.gen_range(0.0..1.0);
+        let root = u.sqrt();
+        let r = scale_radius * root / (1.0 - root);
+        if r < truncation {
+            return r;
+        }
+    }
+}
+
+//inverts the NFW cumulative mass numerically, it has no closed form inverse
+fn sample_nfw_radius(scale_radius: f32, truncation: f32, rng: &mut impl Rng) -> f32 {
+    let cumulative = |r: f32| {
+        let x = r / scale_radius;
+        (1.0 + x).ln() - x / (1.0 + x)
+    };
+    let target = rng.gen_range(0.0..1.0) * cumulative(truncation);
+
+    //bisection, the cumulative mass is monotonic
+    let (mut low, mut high) = (0.0, truncation);
+    for _ in 0..48 {
+        let middle = 0.5 * (low + high);
+        if cumulative(middle) < target {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    0.5 * (low + high)
+}
+
+pub(crate) fn random_on_circle(r: f32, rng: &mut impl Rng) -> [f32; 2] {
+    let angle = rng.gen_range(0.0..2.0 * PI);
+    [r * angle.cos(), r * angle.sin()]
+}
+
+//a random point on a sphere of radius r, seen from above
+pub(crate) fn project_from_sphere(r: f32, rng: &mut impl Rng) -> [f32; 2] {
+    let [x, y, _] = random_on_sphere(r, rng);
+    [x, y]
+}
+
+pub(crate) fn random_on_sphere(r: f32, rng: &mut impl Rng) -> [f32; 3] {
+    let cos_theta: f32 = rng.gen_range(-1.0..1.0);
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    let angle = rng.gen_range(0.0..2.0 * PI);
+    [r * sin_theta * angle.cos(), r * sin_theta * angle.sin(), r * cos_theta]
+}
+
+pub(crate) fn length([x, y]: [f32; 2]) -> f32 {
+    (x * x + y * y).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::diagnostics::testing::sampling_noise;
+    use crate::diagnostics::Diagnostics;
+
+    #[test]
+    fn galaxies_have_their_mass_and_sit_still_at_the_origin() {
+        let galaxy = DiskGalaxy {
+            disk_bodies: 4_000,
+            bulge_bodies: 1_000,
+            halo: Some(NfwHalo { mass: 50_000.0, scale_radius: 10.0, truncation_radius: 100.0, num_bodies: 4_000 }),
+            center_mass: 1_000.0,
+            ..Default::default()
+        };
+        let bodies = galaxy.generate(&mut SmallRng::seed_from_u64(0));
+        assert_eq!(bodies.len(), 9_001);
+
+        let diagnostics = Diagnostics::from_bodies(&bodies, &vec![0.0; bodies.len()]);
+        let expected_mass = galaxy.disk_mass + galaxy.bulge_mass + 50_000.0 + galaxy.center_mass;
+        assert!((diagnostics.mass - expected_mass).abs() < 1e-4 * expected_mass, "{} != {expected_mass}", diagnostics.mass);
+
+        let offset = length(diagnostics.center_of_mass);
+        let position_noise = sampling_noise(&bodies, |body| body.position);
+        assert!(offset < 4.0 * position_noise, "center of mass off by {offset}, noise {position_noise}");
+
+        let drift = length(diagnostics.momentum) / diagnostics.mass;
+        let velocity_noise = sampling_noise(&bodies, |body| body.velocity);
+        assert!(drift < 4.0 * velocity_noise, "bulk velocity {drift}, noise {velocity_noise}");
+    }
+}
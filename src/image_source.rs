@@ -0,0 +1,153 @@
+//bodies sampled from a picture, its brightness being their density. mostly for fun (logos collapsing into a blob),
+//but also handy for checking the renderer against a known shape.
+
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+
+use image::ImageError;
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+use serde::{Deserialize, Serialize};
+
+use crate::file_io::invalid;
+use crate::sim::Body;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImageSource {
+    pub path: PathBuf,
+    pub num_bodies: u32,
+    pub mass: f32,
+    //how wide the image is in the simulation, the height follows from its aspect ratio
+    pub width: f32,
+    //density ~ brightness^gamma
+    #[serde(default = "default_gamma")]
+    pub gamma: f32,
+    //dark pixels are dense instead, for dark shapes on a light background
+    #[serde(default)]
+    pub invert: bool,
+    //bodies on brighter pixels weigh more, instead of all bodies weighing the same
+    #[serde(default)]
+    pub mass_from_brightness: bool,
+    //at rest when left out
+    #[serde(default)]
+    pub velocity_field: Option<VelocityField>,
+}
+
+fn default_gamma() -> f32 {
+    1.0
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "field", rename_all = "snake_case")]
+pub enum VelocityField {
+    //spinning as a solid body, counter-clockwise for positive angular velocities
+    Rigid { angular_velocity: f32 },
+    //isotropic gaussian velocities
+    Random { dispersion: f32 },
+    //falling inwards at a speed proportional to the distance from the center
+    Collapse { rate: f32 },
+}
+
+impl ImageSource {
+    /// Generates the bodies with the image centered on the origin. Fails when the image can't be read or has no
+    /// pixels bright enough to put bodies on.
+    pub fn generate(&self, rng: &mut impl Rng) -> std::io::Result<Vec<Body>> {
+        let image = image::open(&self.path).map_err(|e| {
+            let kind = match &e {
+                ImageError::IoError(e) => e.kind(),
+                _ => ErrorKind::InvalidData,
+            };
+            Error::new(kind, format!("{}: {e}", self.path.display()))
+        })?.into_rgba32f();
+        let (columns, rows) = image.dimensions();
+
+        let brightness: Vec<f32> = image.pixels().map(|pixel| {
+            let [r, g, b, a] = pixel.0;
+            let luminance = (0.2126 * r + 0.7152 * g + 0.0722 * b).clamp(0.0, 1.0);
+            //transparent pixels are empty either way
+            let luminance = if self.invert { 1.0 - luminance } else { luminance };
+            (luminance * a).powf(self.gamma)
+        }).collect();
+
+        let mut cumulative = Vec::with_capacity(brightness.len());
+        let mut total = 0.0f64;
+        for b in &brightness {
+            total += *b as f64;
+            cumulative.push(total);
+        }
+        if total <= 0.0 {
+            return Err(invalid(&format!("{} has no bright pixels to place bodies on", self.path.display())));
+        }
+
+        let pixel_size = self.width / columns as f32;
+        let half_extent = [self.width * 0.5, rows as f32 * pixel_size * 0.5];
+
+        let samples: Vec<([f32; 2], f32)> = (0..self.num_bodies).map(|_| {
+            let target = rng.gen_range(0.0..total);
+            let pixel = cumulative.partition_point(|c| *c <= target).min(brightness.len() - 1);
+            let (column, row) = (pixel as u32 % columns, pixel as u32 / columns);
+
+            //anywhere inside the pixel, with the image's rows going downwards
+            let x = (column as f32 + rng.gen_range(0.0..1.0)) * pixel_size - half_extent[0];
+            let y = half_extent[1] - (row as f32 + rng.gen_range(0.0..1.0)) * pixel_size;
+            ([x, y], brightness[pixel])
+        }).collect();
+
+        let weight_sum: f32 = samples.iter().map(|(_, b)| if self.mass_from_brightness { *b } else { 1.0 }).sum();
+
+        let normal = Normal::new(0.0f32, 1.0).unwrap();
+        Ok(samples.into_iter().map(|(position, b)| {
+            let weight = if self.mass_from_brightness { b } else { 1.0 };
+            let velocity = match self.velocity_field {
+                Some(VelocityField::Rigid { angular_velocity }) => [-angular_velocity * position[1], angular_velocity * position[0]],
+                Some(VelocityField::Random { dispersion }) => [normal.sample(rng) * dispersion, normal.sample(rng) * dispersion],
+                Some(VelocityField::Collapse { rate }) => [-rate * position[0], -rate * position[1]],
+                None => [0.0, 0.0],
+            };
+            Body::new(self.mass * weight / weight_sum, position, velocity)
+        }).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    fn source(path: PathBuf) -> ImageSource {
+        ImageSource {
+            path,
+            num_bodies: 100,
+            mass: 10.0,
+            width: 4.0,
+            gamma: default_gamma(),
+            invert: false,
+            mass_from_brightness: false,
+            velocity_field: None,
+        }
+    }
+
+    #[test]
+    fn bodies_land_on_the_bright_pixels_and_unusable_images_are_errors() {
+        let directory = std::env::temp_dir().join(format!("image_source_test_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let rng = &mut SmallRng::seed_from_u64(0);
+
+        //only the left half is lit
+        let path = directory.join("half.png");
+        RgbaImage::from_fn(4, 4, |x, _| if x < 2 { Rgba([255; 4]) } else { Rgba([0, 0, 0, 255]) }).save(&path).unwrap();
+        let bodies = source(path).generate(rng).unwrap();
+        assert_eq!(bodies.len(), 100);
+        assert!(bodies.iter().all(|b| b.position[0] <= 0.0));
+        assert!((bodies.iter().map(|b| b.mass).sum::<f32>() - 10.0).abs() < 1e-4);
+
+        let path = directory.join("black.png");
+        RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 255])).save(&path).unwrap();
+        assert_eq!(source(path).generate(rng).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        assert_eq!(source(directory.join("missing.png")).generate(rng).unwrap_err().kind(), ErrorKind::NotFound);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
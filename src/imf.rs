@@ -0,0 +1,180 @@
+//initial mass functions, for giving generated bodies realistic per-star masses instead of all weighing the same.
+//masses are sampled in solar masses and then scaled to the requested total.
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::file_io::invalid_input;
+use crate::sim::Body;
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Imf {
+    //Salpeter (1955), a single power law dN/dm ~ m^-2.35
+    Salpeter,
+    //Kroupa (2001), a power law broken at 0.08 and 0.5 solar masses
+    Kroupa,
+    //Chabrier (2003), lognormal below one solar mass and a Salpeter-like tail above
+    Chabrier,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MassFunction {
+    pub imf: Imf,
+    //cutoffs in solar masses
+    #[serde(default = "default_min_mass")]
+    pub min_mass: f32,
+    #[serde(default = "default_max_mass")]
+    pub max_mass: f32,
+    //the sampled masses get scaled to add up to this, when left out the generator keeps its own mass budget
+    #[serde(default)]
+    pub total_mass: Option<f32>,
+}
+
+fn default_min_mass() -> f32 {
+    0.08
+}
+
+fn default_max_mass() -> f32 {
+    100.0
+}
+
+//[lower mass, upper mass, slope, coefficient keeping the pieces continuous]
+const SALPETER: [[f64; 4]; 1] = [[0.0, f64::INFINITY, 2.35, 1.0]];
+const KROUPA: [[f64; 4]; 3] = [
+    [0.0, 0.08, 0.3, 1.0],
+    [0.08, 0.5, 1.3, 0.08],
+    [0.5, f64::INFINITY, 2.3, 0.04],
+];
+
+//the power laws diverge at zero, lower cutoffs get raised to this
+const LOWEST_MASS: f64 = 1e-3;
+
+const CHABRIER_PEAK: f64 = 0.079;
+const CHABRIER_WIDTH: f64 = 0.69;
+const CHABRIER_SLOPE: f64 = 1.3;
+
+impl MassFunction {
+    /// Checks that there is a mass range to sample from.
+    pub fn validate(&self) -> std::io::Result<()> {
+        let (min, max) = (self.min_mass, self.max_mass);
+        if !(min.is_finite() && max.is_finite() && min >= 0.0 && max > min && max as f64 > LOWEST_MASS) {
+            return Err(invalid_input(&format!("the IMF mass range {min} to {max} is empty, max_mass has to be above min_mass and {LOWEST_MASS}")));
+        }
+        if let Some(total) = self.total_mass.filter(|total| !(total.is_finite() && *total > 0.0)) {
+            return Err(invalid_input(&format!("the IMF total mass has to be positive, not {total}")));
+        }
+        Ok(())
+    }
+
+    /// Draws `count` masses in solar masses, without any normalisation.
+    pub fn sample(&self, count: usize, rng: &mut impl Rng) -> Vec<f32> {
+        let min = (self.min_mass as f64).max(LOWEST_MASS);
+        let max = (self.max_mass as f64).max(min);
+        match self.imf {
+            Imf::Salpeter => sample_power_law(&SALPETER, min, max, count, rng),
+            Imf::Kroupa => sample_power_law(&KROUPA, min, max, count, rng),
+            Imf::Chabrier => (0..count).map(|_| sample_chabrier(min, max, rng)).collect(),
+        }
+    }
+
+    /// Draws `count` masses scaled to add up to `total`.
+    pub fn sample_total(&self, count: usize, total: f32, rng: &mut impl Rng) -> Vec<f32> {
+        let mut masses = self.sample(count, rng);
+        let sum: f32 = masses.iter().sum();
+        if sum > 0.0 {
+            masses.iter_mut().for_each(|m| *m *= total / sum);
+        }
+        masses
+    }
+
+    /// Replaces the masses of `bodies`, keeping their combined mass unless `total_mass` is set.
+    pub fn assign(&self, bodies: &mut [Body], rng: &mut impl Rng) {
+        let budget = bodies.iter().map(|b| b.mass).sum();
+        let masses = self.sample_total(bodies.len(), self.total_mass.unwrap_or(budget), rng);
+        for (body, mass) in bodies.iter_mut().zip(masses) {
+            body.mass = mass;
+        }
+    }
+}
+
+//picks a piece by its share of the number of stars between the cutoffs, then inverts that piece's cumulative count
+fn sample_power_law(pieces: &[[f64; 4]], min: f64, max: f64, count: usize, rng: &mut impl Rng) -> Vec<f32> {
+    let clipped: Vec<[f64; 4]> = pieces.iter()
+        .map(|[low, high, slope, k]| [low.max(min), high.min(max), *slope, *k])
+        .filter(|[low, high, _, _]| high > low)
+        .collect();
+    let weights: Vec<f64> = clipped.iter()
+        .map(|[low, high, slope, k]| k * (high.powf(1.0 - slope) - low.powf(1.0 - slope)) / (1.0 - slope))
+        .collect();
+    let total: f64 = weights.iter().sum();
+
+    (0..count).map(|_| {
+        let mut pick = rng.gen_range(0.0..total);
+        let piece = weights.iter().position(|w| {
+            pick -= w;
+            pick < 0.0
+        }).unwrap_or(clipped.len() - 1);
+
+        let [low, high, slope, _] = clipped[piece];
+        let u: f64 = rng.gen_range(0.0..1.0);
+        let (a, b) = (low.powf(1.0 - slope), high.powf(1.0 - slope));
+        (a + u * (b - a)).powf(1.0 / (1.0 - slope)) as f32
+    }).collect()
+}
+
+//rejection sampling in log m, where both pieces are easy to write down and bounded by the lognormal's peak
+fn sample_chabrier(min: f64, max: f64, rng: &mut impl Rng) -> f32 {
+    let per_log_mass = |log_m: f64| {
+        let lognormal = |log_m: f64| (-(log_m - CHABRIER_PEAK.log10()).powi(2) / (2.0 * CHABRIER_WIDTH * CHABRIER_WIDTH)).exp();
+        if log_m <= 0.0 {
+            lognormal(log_m)
+        } else {
+            lognormal(0.0) * 10f64.powf(-CHABRIER_SLOPE * log_m)
+        }
+    };
+
+    let (low, high) = (min.log10(), max.log10());
+    loop {
+        let log_m = rng.gen_range(low..=high);
+        if rng.gen_range(0.0..1.0) < per_log_mass(log_m) {
+            return 10f64.powf(log_m) as f32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    fn mass_function(imf: Imf, min_mass: f32, max_mass: f32) -> MassFunction {
+        MassFunction { imf, min_mass, max_mass, total_mass: None }
+    }
+
+    #[test]
+    fn samples_stay_between_the_cutoffs_and_add_up_to_the_total() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        for imf in [Imf::Salpeter, Imf::Kroupa, Imf::Chabrier] {
+            for (min, max) in [(default_min_mass(), default_max_mass()), (0.01, 0.3), (1.0, 8.0)] {
+                let mass_function = mass_function(imf, min, max);
+                mass_function.validate().unwrap();
+                let masses = mass_function.sample(2_000, rng);
+                assert!(masses.iter().all(|m| (min..=max).contains(m)), "{imf:?} {min}..{max}");
+
+                let masses = mass_function.sample_total(2_000, 500.0, rng);
+                assert!((masses.iter().sum::<f32>() - 500.0).abs() < 0.01, "{imf:?} {min}..{max}");
+            }
+        }
+    }
+
+    #[test]
+    fn empty_mass_ranges_are_rejected() {
+        for (min, max) in [(1.0, 1.0), (5.0, 1.0), (0.0, 1e-4), (-1.0, 1.0), (0.1, f32::NAN)] {
+            assert!(mass_function(Imf::Kroupa, min, max).validate().is_err(), "{min}..{max}");
+        }
+        let mass_function = MassFunction { total_mass: Some(0.0), ..mass_function(Imf::Salpeter, 0.1, 1.0) };
+        assert!(mass_function.validate().is_err());
+    }
+}
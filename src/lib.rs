@@ -2,9 +2,54 @@
 
 mod sim;
 mod renderer;
+mod readback;
 mod camera;
+mod escape;
+mod diagnostics;
+mod mass_evolution;
+mod scenario;
+mod galaxy;
+mod cluster;
+mod imf;
+mod spiral;
+mod encounter;
+mod image_source;
+mod planetary;
+mod canonical;
+mod units;
+mod snapshot;
+mod gadget;
+mod tipsy;
+mod file_io;
+mod particles;
+mod vtk;
+mod density;
+mod frames;
+mod sink;
 
 pub use sim::*;
+pub use escape::*;
+pub use diagnostics::*;
+pub use mass_evolution::*;
+pub use scenario::*;
+pub use galaxy::*;
+pub use cluster::*;
+pub use imf::*;
+pub use spiral::*;
+pub use encounter::*;
+pub use image_source::*;
+pub use planetary::*;
+pub use canonical::*;
+pub use units::*;
+pub use snapshot::*;
+pub use gadget::*;
+pub use tipsy::*;
+pub use particles::*;
+pub use vtk::*;
+pub use density::*;
+pub use frames::*;
+pub use sink::*;
+use std::path::Path;
 use wgpu::{Backends, Device, DeviceDescriptor, Features, InstanceDescriptor, InstanceFlags, PowerPreference, Queue, RequestAdapterOptions};
 use crate::renderer::RenderState;
 
@@ -15,11 +60,19 @@ pub struct State {
 
     pub sim_state: SimState,
     pub render_state: RenderState,
+    //ParaView output written along with every rendered frame
+    pub vtk_series: Option<VtkSeries>,
+    //FITS surface density maps written along with every rendered frame
+    pub density_mapper: Option<DensityMapper>,
 }
 
 const WIDTH: u32 = 1080;
 const HEIGHT: u32 = 1920;
 
+const VTK_DIRECTORY: &str = "output/vtk";
+const VTK_SERIES_NAME: &str = "frames";
+const FITS_DIRECTORY: &str = "output/fits";
+
 const PIXEL_SIZE: usize = std::mem::size_of::<[u8; 4]>();
 const ALIGN: u32 = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
 const UNPADDED_BYTES_PER_ROW: u32 = PIXEL_SIZE as u32 * WIDTH;
@@ -28,49 +81,194 @@ const PADDED_BYTES_PER_ROW: u32 = UNPADDED_BYTES_PER_ROW + PADDING;
 
 
 impl State {
-    pub async fn new() -> Self {
-        let instance = wgpu::Instance::new(InstanceDescriptor {
-            backends: Backends::all(),
-            flags: InstanceFlags::default(),
-            ..Default::default()
-        });
+    pub async fn new(seed: u64) -> Self {
+        let (device, queue) = request_device().await;
 
+        let render_state = RenderState::new(&device);
 
-        let adapter = instance.request_adapter(&RequestAdapterOptions {
-            power_preference: PowerPreference::None,
-            force_fallback_adapter: false,
-            compatible_surface: None,
-        }).await.unwrap();
+        let sim_state = SimState::new(&device, &queue, seed);
 
-        let (device, queue) = adapter.request_device(
-            &DeviceDescriptor {
-                label: None,
-                required_features: Features::empty(),
-                required_limits: Default::default(),
-            },
-            None,
-        ).await.unwrap();
+        Self {
+            device,
+            queue,
+
+            render_state,
+            sim_state,
+            vtk_series: None,
+            density_mapper: None,
+        }
+    }
 
+    pub async fn from_scenario(scenario: &Scenario) -> std::io::Result<Self> {
+        let (device, queue) = request_device().await;
 
-        let render_state = RenderState::new(&device);
+        let mut render_state = RenderState::new(&device);
+        render_state.apply_settings(&queue, &scenario.render);
+        let sink = FrameSink::open(&scenario.render.output, WIDTH, HEIGHT)
+            .unwrap_or_else(|e| panic!("Failed to open frame output {:?}: {e}", scenario.render.output));
+        render_state.readback.set_sink(sink, &device).await?;
 
-        let sim_state = SimState::new(&device);
+        let sim_state = SimState::from_scenario(&device, &queue, scenario)?;
+        let vtk_series = scenario.render.vtk.then(|| VtkSeries::new(VTK_DIRECTORY, VTK_SERIES_NAME));
+        let density_mapper = scenario.render.fits.then(|| DensityMapper::new(&device, WIDTH, HEIGHT));
 
-        Self {
+        Ok(Self {
             device,
             queue,
 
             render_state,
             sim_state,
+            vtk_series,
+            density_mapper,
+        })
+    }
+
+    pub async fn render(&mut self, filename: u32) -> std::io::Result<()> {
+        let info = FrameInfo {
+            frame: filename,
+            time: self.sim_state.time,
+            units: self.sim_state.units,
+            tick_count: self.sim_state.tick_count,
+            num_bodies: self.sim_state.num_bodies(),
+            ..Default::default()
+        };
+        self.render_state.render(&self.device, &self.queue, &self.sim_state.buffers.output_positions, info.num_bodies, info).await?;
+
+        if let Some(vtk_series) = &mut self.vtk_series {
+            let frame = self.sim_state.vtk_frame(&self.device, &self.queue).await;
+            vtk_series.add(self.sim_state.time, filename, &frame)?;
         }
+
+        if let Some(density_mapper) = &self.density_mapper {
+            let framing = Framing::from_camera(&self.render_state.camera_state.camera, density_mapper.height);
+            let map = density_mapper.project(&self.device, &self.queue, &self.sim_state, framing).await;
+            map.save_fits(format!("{FITS_DIRECTORY}/{filename:0>5}.fits"))?;
+        }
+        Ok(())
+    }
+
+    /// Whether the frames are streamed to stdout, which leaves it to them alone.
+    pub fn frames_to_stdout(&self) -> bool {
+        self.render_state.readback.sink().output().to_stdout()
     }
 
-    pub async fn render(&mut self, filename: u32) {
-        self.render_state.render(&self.device, &self.queue, &self.sim_state.output_positions, self.sim_state.bodies.len() as u32);
-        self.render_state.save_buffer_to_image(filename, &self.device).await;
+    /// Waits for every rendered frame to be written out.
+    pub async fn finish_frames(&mut self) -> std::io::Result<()> {
+        self.render_state.readback.flush(&self.device).await
     }
 
-    pub async fn tick(&mut self) {
+    pub async fn tick(&mut self) -> std::io::Result<()> {
         return self.sim_state.tick(&self.device, &self.queue).await;
     }
+
+    /// Restarts a run from a snapshot written by `save_snapshot`.
+    pub async fn from_snapshot(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let snapshot = Snapshot::load(path)?;
+        let (device, queue) = request_device().await;
+
+        let render_state = RenderState::new(&device);
+        let sim_state = SimState::from_snapshot(&device, &queue, &snapshot)?;
+
+        Ok(Self {
+            device,
+            queue,
+
+            render_state,
+            sim_state,
+            vtk_series: None,
+            density_mapper: None,
+        })
+    }
+
+    pub async fn save_snapshot(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        self.sim_state.snapshot(&self.device, &self.queue).await.save(path)
+    }
+
+    /// Starts a run from Gadget-2 initial conditions.
+    pub async fn from_gadget(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let snapshot = GadgetSnapshot::load(path)?;
+        let (device, queue) = request_device().await;
+
+        let render_state = RenderState::new(&device);
+        let sim_state = SimState::from_gadget(&device, &queue, &snapshot);
+
+        Ok(Self {
+            device,
+            queue,
+
+            render_state,
+            sim_state,
+            vtk_series: None,
+            density_mapper: None,
+        })
+    }
+
+    pub async fn save_gadget(&self, path: impl AsRef<Path>, format: GadgetFormat) -> std::io::Result<()> {
+        self.sim_state.to_gadget(&self.device, &self.queue).await.save(path, format)
+    }
+
+    /// Starts a run from a TIPSY snapshot.
+    pub async fn from_tipsy(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let snapshot = TipsySnapshot::load(path)?;
+        let (device, queue) = request_device().await;
+
+        let render_state = RenderState::new(&device);
+        let sim_state = SimState::from_tipsy(&device, &queue, &snapshot);
+
+        Ok(Self {
+            device,
+            queue,
+
+            render_state,
+            sim_state,
+            vtk_series: None,
+            density_mapper: None,
+        })
+    }
+
+    pub async fn save_tipsy(&self, path: impl AsRef<Path>, endianness: Endianness) -> std::io::Result<()> {
+        self.sim_state.to_tipsy(&self.device, &self.queue).await.save(path, endianness)
+    }
+
+    pub async fn save_particles(&self, path: impl AsRef<Path>, format: ParticleFormat) -> std::io::Result<()> {
+        self.sim_state.particle_dump(&self.device, &self.queue).await.save(path, format)
+    }
+
+    /// Energy, momentum and center of mass of every ensemble member, or of everything outside of ensemble mode.
+    pub async fn diagnostics(&self) -> Vec<Diagnostics> {
+        self.sim_state.diagnostics(&self.device, &self.queue).await
+    }
+
+    pub async fn state_hash(&self) -> u64 {
+        self.sim_state.state_hash(&self.device, &self.queue).await
+    }
+}
+
+async fn request_device() -> (Device, Queue) {
+    try_request_device().await.expect("No gpu adapter available")
+}
+
+//none when there is no adapter to run on
+async fn try_request_device() -> Option<(Device, Queue)> {
+    let instance = wgpu::Instance::new(InstanceDescriptor {
+        backends: Backends::all(),
+        flags: InstanceFlags::default(),
+        ..Default::default()
+    });
+
+
+    let adapter = instance.request_adapter(&RequestAdapterOptions {
+        power_preference: PowerPreference::None,
+        force_fallback_adapter: false,
+        compatible_surface: None,
+    }).await?;
+
+    Some(adapter.request_device(
+        &DeviceDescriptor {
+            label: None,
+            required_features: Features::empty(),
+            required_limits: Default::default(),
+        },
+        None,
+    ).await.unwrap())
 }
@@ -1,19 +1,117 @@
 use std::time::Instant;
-use wgpu_n_body::State;
+use wgpu_n_body::{EscapeSettings, ParticleFormat, Scenario, State};
+
+const SEED: u64 = 0;
+//slower, but reruns with the same seed print the same state hashes
+const DETERMINISTIC: bool = false;
+//a run can be restarted from a checkpoint by passing it instead of a scenario
+const CHECKPOINT_PATH: &str = "output/checkpoint.snap";
+//particle dumps for analysis are written to output/particles/
+const PARTICLE_DUMP_FORMAT: ParticleFormat = ParticleFormat::Npz;
+//with --escape-radius, escaping bodies are checked for this often and logged here
+const ESCAPE_INTERVAL: u32 = 10;
+const ESCAPE_LOG_PATH: &str = "output/escapers.csv";
+
+//checkpoints, particle dumps and diagnostics are only written when asked for, with the iterations between two of them
+#[derive(Default)]
+struct Options {
+    //snapshot, scenario file or preset name
+    input: Option<String>,
+    checkpoint_interval: Option<u32>,
+    particle_dump_interval: Option<u32>,
+    diagnostics_interval: Option<u32>,
+    //removes bodies this far from their system's center of mass, in place of the scenario's escape settings
+    escape_radius: Option<f32>,
+}
+
+impl Options {
+    fn from_args() -> Self {
+        let mut options = Options::default();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().unwrap_or_else(|| panic!("{arg} needs a value"));
+            let interval = |value: String| {
+                let interval = value.parse::<u32>().unwrap_or_else(|e| panic!("{arg} {value}: {e}"));
+                Some(interval.max(1))
+            };
+            match arg.as_str() {
+                "--checkpoint-every" => options.checkpoint_interval = interval(value()),
+                "--dump-every" => options.particle_dump_interval = interval(value()),
+                "--diagnostics-every" => options.diagnostics_interval = interval(value()),
+                "--escape-radius" => {
+                    let value = value();
+                    options.escape_radius = Some(value.parse().unwrap_or_else(|e| panic!("{arg} {value}: {e}")));
+                }
+                _ if arg.starts_with("--") => panic!("unknown option {arg}, the options are --checkpoint-every, --dump-every, --diagnostics-every and --escape-radius"),
+                _ => options.input = Some(arg),
+            }
+        }
+        options
+    }
+}
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
-    let mut state = State::new().await;
+    let options = Options::from_args();
+    //an optional snapshot, scenario file or preset name replaces the built in setup
+    let mut state = match options.input {
+        Some(path) if path.ends_with(".snap") => {
+            State::from_snapshot(&path).await.unwrap_or_else(|e| panic!("Failed to load snapshot {path}: {e}"))
+        }
+        Some(path) if std::path::Path::new(&path).exists() => {
+            let scenario = Scenario::from_file(&path).unwrap_or_else(|e| panic!("Failed to load scenario {path}: {e}"));
+            State::from_scenario(&scenario).await.unwrap_or_else(|e| panic!("Failed to set up scenario {path}: {e}"))
+        }
+        Some(name) => {
+            let scenario = Scenario::preset(&name).unwrap_or_else(|| {
+                panic!("{name} is neither a scenario file nor a preset, the presets are: {}", Scenario::preset_names().collect::<Vec<_>>().join(", "))
+            });
+            State::from_scenario(&scenario).await.unwrap_or_else(|e| panic!("Failed to set up preset {name}: {e}"))
+        }
+        None => {
+            let mut state = State::new(SEED).await;
+            state.sim_state.deterministic = DETERMINISTIC;
+            state
+        }
+    };
+    if let Some(radius) = options.escape_radius {
+        state.sim_state.set_escape_settings(&state.queue, Some(EscapeSettings {
+            radius,
+            interval: ESCAPE_INTERVAL,
+            log_path: Some(ESCAPE_LOG_PATH.into()),
+        })).unwrap_or_else(|e| panic!("Failed to open the escape log: {e}"));
+    }
     let total_runtime = Instant::now();
-    let mut i = 0;
+    //progress goes to stderr when stdout carries a video stream
+    let frames_to_stdout = state.frames_to_stdout();
+    let report = |line: String| if frames_to_stdout { eprintln!("{line}") } else { println!("{line}") };
+    //picks up the numbering where a restarted run left off
+    let mut i = state.sim_state.tick_count as u32;
     loop {
         i += 1;
         let start_instant = Instant::now();
-        state.tick().await;
-        state.render(i).await;
+        state.tick().await.unwrap_or_else(|e| panic!("Failed to log escapers: {e}"));
+        state.render(i).await.unwrap_or_else(|e| panic!("Failed to write the output of iteration #{i}: {e}"));
         let runtime = start_instant.elapsed().as_secs_f32();
-        println!("Finished iteration #{} in {runtime}s. Total runtime - {:?}", i, total_runtime.elapsed());
+        //reading the hash back is only worth it when reruns can be compared against it
+        if state.sim_state.deterministic && i.is_multiple_of(100) {
+            let time = state.sim_state.units.format_time(state.sim_state.time);
+            report(format!("State hash after iteration #{} (t = {time}) - {:016x}", i, state.state_hash().await));
+        }
+        if options.diagnostics_interval.is_some_and(|interval| i.is_multiple_of(interval)) {
+            for (member, diagnostics) in state.diagnostics().await.iter().enumerate() {
+                report(format!("Member #{member} after iteration #{i}: {}", state.sim_state.units.describe(diagnostics)));
+            }
+        }
+        if options.particle_dump_interval.is_some_and(|interval| i.is_multiple_of(interval)) {
+            let path = format!("output/particles/{i:06}.{}", PARTICLE_DUMP_FORMAT.extension());
+            state.save_particles(path, PARTICLE_DUMP_FORMAT).await.unwrap();
+        }
+        if options.checkpoint_interval.is_some_and(|interval| i.is_multiple_of(interval)) {
+            state.save_snapshot(CHECKPOINT_PATH).await.unwrap();
+        }
+        report(format!("Finished iteration #{} in {runtime}s. Total runtime - {:?}. Escapers - {}", i, total_runtime.elapsed(), state.sim_state.escape.stats.escaped));
     }
 }
 
@@ -0,0 +1,121 @@
+//models for bodies losing (or gaining) mass over time.
+//every model maps the simulation time and a body's initial mass to its current mass.
+
+use crate::units::UnitSystem;
+
+pub enum MassEvolution {
+    /// Every body follows the same table of `[time, fraction of the initial mass left]`, linearly interpolated
+    /// and clamped at both ends. The table has to be sorted by time.
+    Table(Vec<[f32; 2]>),
+    /// Bodies are treated as stars that turn into remnants once they leave the main sequence. Masses and times are
+    /// in the given units, which need to be physical ones. Bodies outside the stellar mass range (for example central
+    /// black holes) keep their mass.
+    Stellar(UnitSystem),
+    /// User supplied function of `(time, initial mass) -> mass`.
+    Custom(Box<dyn Fn(f32, f32) -> f32>),
+}
+
+//stars above this lose their mass to winds long before anything interesting happens, and bodies this heavy are
+//much more likely to be black holes or other "special" bodies
+const MAX_STELLAR_MASS: f32 = 150.0;
+const MIN_STELLAR_MASS: f32 = 0.08;
+//mass loss after the main sequence is spread over this fraction of the main sequence lifetime
+const POST_MAIN_SEQUENCE_FRACTION: f32 = 0.1;
+
+impl MassEvolution {
+    pub fn mass(&self, time: f32, initial_mass: f32) -> f32 {
+        match self {
+            MassEvolution::Table(table) => initial_mass * interpolate(table, time),
+            MassEvolution::Stellar(units) => {
+                let solar_mass = units.solar_mass();
+                let m = initial_mass / solar_mass;
+                if !(MIN_STELLAR_MASS..=MAX_STELLAR_MASS).contains(&m) {
+                    return initial_mass;
+                }
+
+                let age = time / units.gyr();
+                let lifetime = main_sequence_lifetime(m);
+                let progress = ((age - lifetime) / (lifetime * POST_MAIN_SEQUENCE_FRACTION)).clamp(0.0, 1.0);
+                let remnant = remnant_mass(m);
+
+                (m + (remnant - m) * progress) * solar_mass
+            }
+            MassEvolution::Custom(f) => f(time, initial_mass),
+        }
+    }
+}
+
+//in Gyr, for a star of `m` solar masses
+fn main_sequence_lifetime(m: f32) -> f32 {
+    10.0 * m.powf(-2.5)
+}
+
+//in solar masses. white dwarf initial-final mass relation, then neutron stars and a crude black hole fraction.
+fn remnant_mass(m: f32) -> f32 {
+    let remnant = if m < 8.0 {
+        0.109 * m + 0.394
+    } else if m < 25.0 {
+        1.4
+    } else {
+        0.1 * m
+    };
+    remnant.min(m)
+}
+
+fn interpolate(table: &[[f32; 2]], time: f32) -> f32 {
+    let Some(first) = table.first() else {
+        return 1.0;
+    };
+    if time <= first[0] {
+        return first[1];
+    }
+
+    for pair in table.windows(2) {
+        let [[t0, f0], [t1, f1]] = [pair[0], pair[1]];
+        if time <= t1 {
+            let t = if t1 > t0 { (time - t0) / (t1 - t0) } else { 1.0 };
+            return f0 + (f1 - f0) * t;
+        }
+    }
+
+    table[table.len() - 1][1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_are_interpolated_and_clamped() {
+        let table = [[1.0, 1.0], [3.0, 0.5], [3.0, 0.4], [5.0, 0.2]];
+        assert_eq!(interpolate(&[], 2.0), 1.0);
+        assert_eq!(interpolate(&table, 0.0), 1.0);
+        assert_eq!(interpolate(&table, 2.0), 0.75);
+        assert_eq!(interpolate(&table, 3.0), 0.5);
+        assert!((interpolate(&table, 4.0) - 0.3).abs() < 1e-6);
+        assert_eq!(interpolate(&table, 9.0), 0.2);
+        assert_eq!(MassEvolution::Table(table.to_vec()).mass(2.0, 4.0), 3.0);
+    }
+
+    #[test]
+    fn stars_lose_mass_after_the_main_sequence() {
+        let units = UnitSystem::Galactic;
+        let stellar = MassEvolution::Stellar(units);
+        let solar_mass = units.solar_mass();
+        //a 2 solar mass star lives about 1.8 Gyr, then sheds mass over another 0.18 Gyr
+        let lifetime = main_sequence_lifetime(2.0);
+        let mass = |age: f32| stellar.mass(age * units.gyr(), 2.0 * solar_mass) / solar_mass;
+        assert_eq!(mass(0.0), 2.0);
+        assert_eq!(mass(lifetime), 2.0);
+        let halfway = mass(lifetime * (1.0 + POST_MAIN_SEQUENCE_FRACTION * 0.5));
+        assert!(halfway < 2.0 && halfway > remnant_mass(2.0));
+        assert!((mass(lifetime * 2.0) - remnant_mass(2.0)).abs() < 1e-5);
+
+        //heavier stars die first
+        assert!(main_sequence_lifetime(10.0) < lifetime);
+        assert_eq!(remnant_mass(15.0), 1.4);
+        //too heavy or too light to be a star
+        assert_eq!(stellar.mass(100.0, 1000.0 * solar_mass), 1000.0 * solar_mass);
+        assert_eq!(stellar.mass(100.0, 0.01 * solar_mass), 0.01 * solar_mass);
+    }
+}
@@ -0,0 +1,516 @@
+//particle dumps for analysis in Python: CSV, a NumPy structured array (.npy) or a NumPy archive with one array per
+//quantity (.npz, what np.savez_compressed writes). every dump has the id, species tag, mass, position and velocity of
+//each body. the same files can be read back into bodies, so initial conditions can come from a notebook. the reader
+//matches columns by name, takes x and y or a (n, 2) position array (same for velocities), and any numeric dtype.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use wgpu::{Device, Queue};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::file_io::invalid;
+use crate::sim::{Body, SimState};
+
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+//the header (with the magic and lengths) gets padded to a multiple of this
+const NPY_ALIGNMENT: usize = 64;
+const CSV_HEADER: &str = "id,species,mass,x,y,vx,vy";
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ParticleFormat {
+    Csv,
+    Npy,
+    #[default]
+    Npz,
+}
+
+impl ParticleFormat {
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        match path.as_ref().extension()?.to_str()? {
+            "csv" => Some(ParticleFormat::Csv),
+            "npy" => Some(ParticleFormat::Npy),
+            "npz" => Some(ParticleFormat::Npz),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ParticleFormat::Csv => "csv",
+            ParticleFormat::Npy => "npy",
+            ParticleFormat::Npz => "npz",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ParticleDump {
+    pub ids: Vec<u32>,
+    pub bodies: Vec<Body>,
+}
+
+impl ParticleDump {
+    pub fn save(&self, path: impl AsRef<Path>, format: ParticleFormat) -> std::io::Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        match format {
+            ParticleFormat::Csv => self.write_csv(&mut writer)?,
+            ParticleFormat::Npy => self.write_npy(&mut writer)?,
+            ParticleFormat::Npz => self.write_npz(&mut writer)?,
+        }
+        writer.flush()
+    }
+
+    /// Reads a dump, or any file with the same columns, the format going by the extension.
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let format = ParticleFormat::from_path(&path).ok_or_else(|| invalid("expected a .csv, .npy or .npz file"))?;
+        let columns = match format {
+            ParticleFormat::Csv => read_csv(&mut BufReader::new(File::open(path)?))?,
+            ParticleFormat::Npy => read_npy(&std::fs::read(path)?)?.columns(""),
+            ParticleFormat::Npz => read_npz(BufReader::new(File::open(path)?))?,
+        };
+        Self::from_columns(columns)
+    }
+
+    pub fn write_csv(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writeln!(writer, "{CSV_HEADER}")?;
+        for (id, body) in self.ids().zip(&self.bodies) {
+            let [x, y] = body.position;
+            let [vx, vy] = body.velocity;
+            writeln!(writer, "{id},{},{},{x},{y},{vx},{vy}", body.species, body.mass)?;
+        }
+        Ok(())
+    }
+
+    /// A single structured array with one field per column.
+    pub fn write_npy(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        let fields = CSV_HEADER.split(',').enumerate().map(|(i, name)| {
+            format!("('{name}', '{}')", if i < 2 { "<u4" } else { "<f4" })
+        }).collect::<Vec<_>>();
+        write_npy_header(writer, &format!("[{}]", fields.join(", ")), &[self.bodies.len()])?;
+
+        for (id, body) in self.ids().zip(&self.bodies) {
+            writer.write_all(&id.to_le_bytes())?;
+            writer.write_all(&body.species.to_le_bytes())?;
+            for value in [body.mass, body.position[0], body.position[1], body.velocity[0], body.velocity[1]] {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// An archive with the arrays `id`, `species`, `mass`, `position` and `velocity`.
+    pub fn write_npz(&self, writer: &mut (impl Write + std::io::Seek)) -> std::io::Result<()> {
+        let n = self.bodies.len();
+        let ids = self.ids().flat_map(u32::to_le_bytes).collect::<Vec<_>>();
+        let species = self.bodies.iter().flat_map(|b| b.species.to_le_bytes()).collect::<Vec<_>>();
+        let masses = self.bodies.iter().flat_map(|b| b.mass.to_le_bytes()).collect::<Vec<_>>();
+        let positions = self.bodies.iter().flat_map(|b| b.position).flat_map(f32::to_le_bytes).collect::<Vec<_>>();
+        let velocities = self.bodies.iter().flat_map(|b| b.velocity).flat_map(f32::to_le_bytes).collect::<Vec<_>>();
+
+        let mut zip = ZipWriter::new(writer);
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        for (name, descr, shape, data) in [
+            ("id", "'<u4'", vec![n], ids),
+            ("species", "'<u4'", vec![n], species),
+            ("mass", "'<f4'", vec![n], masses),
+            ("position", "'<f4'", vec![n, 2], positions),
+            ("velocity", "'<f4'", vec![n, 2], velocities),
+        ] {
+            zip.start_file(format!("{name}.npy"), options)?;
+            write_npy_header(&mut zip, descr, &shape)?;
+            zip.write_all(&data)?;
+        }
+        zip.finish()?;
+        Ok(())
+    }
+
+    //ids count up from 0 for dumps made without them
+    fn ids(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.bodies.len()).map(|i| self.ids.get(i).copied().unwrap_or(i as u32))
+    }
+
+    fn from_columns(columns: Vec<(String, Vec<f64>)>) -> std::io::Result<Self> {
+        let column = |names: &[&str]| {
+            columns.iter().find(|(name, _)| names.contains(&name.as_str())).map(|(_, values)| values)
+        };
+        let x = column(&["x", "position.0", "pos.0"]).ok_or_else(|| invalid("no x column"))?;
+        let y = column(&["y", "position.1", "pos.1"]).ok_or_else(|| invalid("no y column"))?;
+        let mass = column(&["mass", "m"]).ok_or_else(|| invalid("no mass column"))?;
+        let vx = column(&["vx", "velocity.0", "vel.0"]);
+        let vy = column(&["vy", "velocity.1", "vel.1"]);
+        let species = column(&["species", "tag"]);
+        let ids = column(&["id", "ids", "iord"]);
+
+        let n = x.len();
+        if [y, mass].iter().chain(&[vx, vy, species, ids].map(|c| c.unwrap_or(x))).any(|c| c.len() != n) {
+            return Err(invalid("columns have different lengths"));
+        }
+
+        let at = |column: Option<&Vec<f64>>, i: usize| column.map_or(0.0, |c| c[i]);
+        let bodies = (0..n).map(|i| {
+            Body::new(mass[i] as f32, [x[i] as f32, y[i] as f32], [at(vx, i) as f32, at(vy, i) as f32]).with_species(at(species, i) as u32)
+        }).collect();
+        Ok(Self {
+            ids: ids.map(|ids| ids.iter().map(|id| *id as u32).collect()).unwrap_or_default(),
+            bodies,
+        })
+    }
+}
+
+impl SimState {
+    /// Reads the live bodies and their ids back from the gpu.
+    pub async fn particle_dump(&self, device: &Device, queue: &Queue) -> ParticleDump {
+        ParticleDump {
+            ids: self.read_ids(device, queue).await,
+            bodies: self.read_bodies(device, queue).await,
+        }
+    }
+}
+
+/// Bodies from a CSV, .npy or .npz file, for the `particles` scenario generator.
+pub fn load_bodies(path: impl AsRef<Path>) -> std::io::Result<Vec<Body>> {
+    Ok(ParticleDump::load(path)?.bodies)
+}
+
+fn read_csv(reader: &mut impl BufRead) -> std::io::Result<Vec<(String, Vec<f64>)>> {
+    let mut lines = reader.lines();
+    let header = lines.next().ok_or_else(|| invalid("empty csv file"))??;
+    let mut columns = header.split(',').map(|name| (name.trim().to_lowercase(), Vec::new())).collect::<Vec<_>>();
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let values = line.split(',').map(|v| v.trim().parse::<f64>()).collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid(&format!("bad number in \"{line}\": {e}")))?;
+        if values.len() != columns.len() {
+            return Err(invalid(&format!("expected {} values in \"{line}\"", columns.len())));
+        }
+        for ((_, column), value) in columns.iter_mut().zip(values) {
+            column.push(value);
+        }
+    }
+    Ok(columns)
+}
+
+fn read_npz(reader: impl Read + std::io::Seek) -> std::io::Result<Vec<(String, Vec<f64>)>> {
+    let mut archive = ZipArchive::new(reader)?;
+    let mut columns = Vec::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let Some(name) = file.name().strip_suffix(".npy").map(str::to_string) else {
+            continue;
+        };
+        //the entries can't seek, the npy reader wants its data in one piece anyway
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        columns.extend(read_npy(&data)?.columns(&name));
+    }
+    Ok(columns)
+}
+
+fn write_npy_header(writer: &mut impl Write, descr: &str, shape: &[usize]) -> std::io::Result<()> {
+    let shape = match shape {
+        [n] => format!("({n},)"),
+        _ => format!("({})", shape.iter().map(usize::to_string).collect::<Vec<_>>().join(", ")),
+    };
+    let mut header = format!("{{'descr': {descr}, 'fortran_order': False, 'shape': {shape}, }}");
+    //magic, version and length come first, the header ends with a newline
+    let unpadded = NPY_MAGIC.len() + 2 + 2 + header.len() + 1;
+    header.push_str(&" ".repeat(unpadded.next_multiple_of(NPY_ALIGNMENT) - unpadded));
+    header.push('\n');
+
+    writer.write_all(NPY_MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())
+}
+
+//a numpy array read into doubles, one entry per field of a structured array or a single unnamed one
+struct NpyArray {
+    rows: usize,
+    //name, values per row, values
+    fields: Vec<(String, usize, Vec<f64>)>,
+}
+
+impl NpyArray {
+    //flat columns, fields with several values per row get split into `name.0`, `name.1`...
+    fn columns(self, array_name: &str) -> Vec<(String, Vec<f64>)> {
+        let rows = self.rows;
+        self.fields.into_iter().flat_map(|(name, width, values)| {
+            let name = if name.is_empty() { array_name.to_string() } else { name };
+            if width == 1 {
+                vec![(name, values)]
+            } else {
+                (0..width).map(|j| (format!("{name}.{j}"), (0..rows).map(|i| values[i * width + j]).collect())).collect()
+            }
+        }).collect()
+    }
+}
+
+//element type of a field, like "<f4"
+#[derive(Copy, Clone)]
+struct Dtype {
+    kind: u8,
+    size: usize,
+    big_endian: bool,
+}
+
+impl Dtype {
+    fn parse(descr: &str) -> std::io::Result<Self> {
+        let bytes = descr.as_bytes();
+        let size = descr.get(2..).and_then(|size| size.parse().ok())
+            .ok_or_else(|| invalid(&format!("unsupported dtype {descr}")))?;
+        let dtype = Self { kind: bytes[1], size, big_endian: bytes[0] == b'>' };
+        match (dtype.kind, size) {
+            (b'f', 4 | 8) | (b'i' | b'u', 1 | 2 | 4 | 8) | (b'b', 1) => Ok(dtype),
+            _ => Err(invalid(&format!("unsupported dtype {descr}"))),
+        }
+    }
+
+    fn read(&self, bytes: &[u8]) -> f64 {
+        let mut buffer = [0u8; 8];
+        buffer[..self.size].copy_from_slice(bytes);
+        if self.big_endian {
+            buffer[..self.size].reverse();
+        }
+        let unsigned = u64::from_le_bytes(buffer);
+        match (self.kind, self.size) {
+            (b'f', 4) => f32::from_bits(unsigned as u32) as f64,
+            (b'f', _) => f64::from_bits(unsigned),
+            (b'i', size) => {
+                //sign extend
+                let shift = 64 - size * 8;
+                ((unsigned << shift) as i64 >> shift) as f64
+            }
+            _ => unsigned as f64,
+        }
+    }
+}
+
+fn read_npy(bytes: &[u8]) -> std::io::Result<NpyArray> {
+    let reader = &mut &*bytes;
+    let mut preamble = [0; 8];
+    reader.read_exact(&mut preamble)?;
+    if &preamble[..6] != NPY_MAGIC {
+        return Err(invalid("not a .npy file"));
+    }
+    let header_len = if preamble[6] == 1 {
+        let mut len = [0; 2];
+        reader.read_exact(&mut len)?;
+        u16::from_le_bytes(len) as usize
+    } else {
+        let mut len = [0; 4];
+        reader.read_exact(&mut len)?;
+        u32::from_le_bytes(len) as usize
+    };
+    let mut header = vec![0; header_len];
+    reader.read_exact(&mut header)?;
+    let header = PyValue::parse(&String::from_utf8_lossy(&header))?;
+
+    let entry = |key: &str| header.get(key).ok_or_else(|| invalid(&format!("no {key} in the .npy header")));
+    if matches!(entry("fortran_order")?, PyValue::Bool(true)) {
+        return Err(invalid("fortran ordered arrays are not supported"));
+    }
+    let shape = entry("shape")?.list().iter().map(PyValue::int).collect::<Vec<_>>();
+    let rows = shape.first().copied().unwrap_or(1);
+    let row_width = product(shape.iter().skip(1).copied()).ok_or_else(|| invalid("the .npy shape is too large"))?;
+
+    //name, dtype and values per row of every field
+    let fields = match entry("descr")? {
+        PyValue::Str(descr) => vec![(String::new(), Dtype::parse(descr)?, row_width)],
+        PyValue::List(fields) => fields.iter().map(|field| {
+            let parts = field.list();
+            let (Some(PyValue::Str(name)), Some(PyValue::Str(descr))) = (parts.first(), parts.get(1)) else {
+                return Err(invalid("nested structured dtypes are not supported"));
+            };
+            let width = parts.get(2).map_or(Some(1), |shape| product(shape.list().iter().map(PyValue::int)))
+                .and_then(|width| width.checked_mul(row_width))
+                .ok_or_else(|| invalid("the .npy shape is too large"))?;
+            Ok((name.clone(), Dtype::parse(descr)?, width))
+        }).collect::<std::io::Result<Vec<_>>>()?,
+        _ => return Err(invalid("bad descr in the .npy header")),
+    };
+
+    //the sizes come from the header, checked against what the file holds before anything gets allocated
+    let row_size = fields.iter().try_fold(0usize, |size, (_, dtype, width)| size.checked_add(dtype.size.checked_mul(*width)?));
+    let data_size = row_size.and_then(|row_size| row_size.checked_mul(rows));
+    let (Some(row_size), Some(data_size)) = (row_size, data_size) else {
+        return Err(invalid("the .npy shape is too large"));
+    };
+    if row_size == 0 && rows > 0 {
+        return Err(invalid("the .npy array has empty rows"));
+    }
+    let data = reader.get(..data_size)
+        .ok_or_else(|| invalid(&format!("the .npy array needs {data_size} bytes, the file has {} left", reader.len())))?;
+
+    let mut offset = 0;
+    let fields = fields.into_iter().map(|(name, dtype, width)| {
+        let values = (0..rows).flat_map(|row| (0..width).map(move |j| (row, j))).map(|(row, j)| {
+            let start = row * row_size + offset + j * dtype.size;
+            dtype.read(&data[start..start + dtype.size])
+        }).collect();
+        offset += dtype.size * width;
+        (name, width, values)
+    }).collect();
+
+    Ok(NpyArray { rows, fields })
+}
+
+fn product(mut values: impl Iterator<Item = usize>) -> Option<usize> {
+    values.try_fold(1usize, usize::checked_mul)
+}
+
+//just enough of Python's literal syntax for .npy headers. tuples are read as lists
+enum PyValue {
+    Str(String),
+    Int(usize),
+    Bool(bool),
+    List(Vec<PyValue>),
+    Dict(Vec<(String, PyValue)>),
+}
+
+impl PyValue {
+    fn parse(text: &str) -> std::io::Result<Self> {
+        let mut chars = text.chars().peekable();
+        Self::parse_value(&mut chars)
+    }
+
+    fn parse_value(chars: &mut std::iter::Peekable<std::str::Chars>) -> std::io::Result<Self> {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.next() {
+            Some(quote @ ('\'' | '"')) => Ok(PyValue::Str(chars.by_ref().take_while(|c| *c != quote).collect())),
+            Some(open @ ('(' | '[' | '{')) => {
+                let close = match open {
+                    '(' => ')',
+                    '[' => ']',
+                    _ => '}',
+                };
+                let mut items = Vec::new();
+                let mut entries = Vec::new();
+                loop {
+                    while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+                    if chars.next_if_eq(&close).is_some() {
+                        break;
+                    }
+                    let value = Self::parse_value(chars)?;
+                    if open == '{' {
+                        let PyValue::Str(key) = value else {
+                            return Err(invalid("dictionary keys have to be strings"));
+                        };
+                        while chars.next_if(|c| c.is_whitespace() || *c == ':').is_some() {}
+                        entries.push((key, Self::parse_value(chars)?));
+                    } else {
+                        items.push(value);
+                    }
+                }
+                Ok(if open == '{' { PyValue::Dict(entries) } else { PyValue::List(items) })
+            }
+            Some(c) if c.is_alphanumeric() => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric()) {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "True" => Ok(PyValue::Bool(true)),
+                    "False" => Ok(PyValue::Bool(false)),
+                    //the L suffix is from Python 2
+                    _ => word.trim_end_matches('L').parse().map(PyValue::Int).map_err(|_| invalid(&format!("unexpected {word} in the .npy header"))),
+                }
+            }
+            _ => Err(invalid("malformed .npy header")),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&PyValue> {
+        match self {
+            PyValue::Dict(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn list(&self) -> &[PyValue] {
+        match self {
+            PyValue::List(items) => items,
+            _ => &[],
+        }
+    }
+
+    fn int(&self) -> usize {
+        match self {
+            PyValue::Int(value) => *value,
+            _ => 0,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn dump() -> ParticleDump {
+        ParticleDump {
+            ids: vec![7, 3, 11],
+            bodies: vec![
+                Body::new(1.5, [1.0, -2.0], [0.25, 4.0]).with_species(1),
+                Body::new(2.0, [-5.5, 6.0], [7.0, -0.5]).with_species(0),
+                Body::new(0.125, [9.0, 10.0], [-11.0, 12.0]).with_species(3),
+            ],
+        }
+    }
+
+    fn assert_same(read: ParticleDump, dump: &ParticleDump) {
+        assert_eq!(read.ids, dump.ids);
+        assert_eq!(bytemuck::cast_slice::<Body, u8>(&read.bodies), bytemuck::cast_slice::<Body, u8>(&dump.bodies));
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let dump = dump();
+        let mut data = Vec::new();
+        dump.write_csv(&mut data).unwrap();
+        assert_same(ParticleDump::from_columns(read_csv(&mut data.as_slice()).unwrap()).unwrap(), &dump);
+    }
+
+    #[test]
+    fn npy_round_trip() {
+        let dump = dump();
+        let mut data = Vec::new();
+        dump.write_npy(&mut data).unwrap();
+        assert_same(ParticleDump::from_columns(read_npy(&data).unwrap().columns("")).unwrap(), &dump);
+    }
+
+    #[test]
+    fn npz_round_trip() {
+        let dump = dump();
+        let mut data = Cursor::new(Vec::new());
+        dump.write_npz(&mut data).unwrap();
+        data.set_position(0);
+        assert_same(ParticleDump::from_columns(read_npz(data).unwrap()).unwrap(), &dump);
+    }
+
+    #[test]
+    fn malformed_npy_headers_are_rejected() {
+        let npy = |descr: &str, shape: &[usize], data: &[u8]| {
+            let mut bytes = Vec::new();
+            write_npy_header(&mut bytes, descr, shape).unwrap();
+            bytes.extend_from_slice(data);
+            read_npy(&bytes).err().map(|e| e.kind())
+        };
+        assert_eq!(npy("[()]", &[1], &[0; 4]), Some(std::io::ErrorKind::InvalidData));
+        assert_eq!(npy("'<f'", &[1], &[0; 4]), Some(std::io::ErrorKind::InvalidData));
+        assert_eq!(npy("'<\u{e9}4'", &[1], &[0; 4]), Some(std::io::ErrorKind::InvalidData));
+        //more rows than the file has data for
+        assert_eq!(npy("'<f4'", &[1 << 40], &[0; 4]), Some(std::io::ErrorKind::InvalidData));
+        assert_eq!(npy("'<f4'", &[usize::MAX, 2], &[0; 4]), Some(std::io::ErrorKind::InvalidData));
+        assert_eq!(npy("'<f4'", &[1], &[0; 4]), None);
+    }
+}
@@ -0,0 +1,252 @@
+//planetary systems: a central body with planets on given orbital elements, belts of small bodies, and rings with
+//shepherd moons around the planets. everything starts on exact Keplerian orbits around whatever it circles.
+//bodies closer than the simulation's min_distance don't interact, so it has to be well below the closest orbits.
+
+use std::f32::consts::PI;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::file_io::invalid_input;
+use crate::sim::Body;
+
+//shepherd moons sit this far inside and outside the edges of their ring, as a fraction of its radius
+const SHEPHERD_GAP: f32 = 0.03;
+
+/// Classical orbital elements, angles in degrees. The reference plane is the simulation plane.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OrbitalElements {
+    pub semi_major_axis: f32,
+    pub eccentricity: f32,
+    pub inclination: f32,
+    pub longitude_of_node: f32,
+    pub argument_of_periapsis: f32,
+    pub mean_anomaly: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlanetarySystem {
+    pub star_mass: f32,
+    #[serde(default)]
+    pub planets: Vec<Planet>,
+    #[serde(default)]
+    pub belts: Vec<Belt>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Planet {
+    pub mass: f32,
+    #[serde(flatten)]
+    pub orbit: OrbitalElements,
+    //around this planet
+    #[serde(default)]
+    pub moons: Vec<Planet>,
+    #[serde(default)]
+    pub belts: Vec<Belt>,
+}
+
+/// A belt of small bodies spread evenly in semi-major axis, or a ring if both dispersions are 0.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Belt {
+    pub num_bodies: u32,
+    //of the whole belt, 0 for test particles
+    #[serde(default)]
+    pub mass: f32,
+    pub inner_radius: f32,
+    pub outer_radius: f32,
+    //eccentricities and inclinations (in degrees) follow Rayleigh distributions with these scales
+    #[serde(default)]
+    pub eccentricity_dispersion: f32,
+    #[serde(default)]
+    pub inclination_dispersion: f32,
+    //mass of the two moons just inside and outside the belt, 0 for none
+    #[serde(default)]
+    pub shepherd_mass: f32,
+}
+
+impl OrbitalElements {
+    /// Position and velocity relative to the body being orbited, seen from above. `mu` is the combined mass.
+    pub fn state_vector(&self, mu: f32) -> ([f32; 2], [f32; 2]) {
+        let a = self.semi_major_axis;
+        let e = self.eccentricity.clamp(0.0, 0.999);
+
+        //Kepler's equation, M = E - e sin E
+        let mean_anomaly = self.mean_anomaly.to_radians();
+        let mut eccentric_anomaly = if e > 0.8 { PI } else { mean_anomaly };
+        for _ in 0..32 {
+            let step = (eccentric_anomaly - e * eccentric_anomaly.sin() - mean_anomaly) / (1.0 - e * eccentric_anomaly.cos());
+            eccentric_anomaly -= step;
+            if step.abs() < 1e-7 {
+                break;
+            }
+        }
+
+        let (sin_e, cos_e) = eccentric_anomaly.sin_cos();
+        let minor_factor = (1.0 - e * e).sqrt();
+        let anomaly_rate = (mu / (a * a * a)).sqrt() / (1.0 - e * cos_e);
+        //in the orbital plane, periapsis along x
+        let position = [a * (cos_e - e), a * minor_factor * sin_e, 0.0];
+        let velocity = [-a * sin_e * anomaly_rate, a * minor_factor * cos_e * anomaly_rate, 0.0];
+
+        let rotate = |v: [f32; 3]| {
+            let v = rotate_z(v, self.argument_of_periapsis.to_radians());
+            let v = rotate_x(v, self.inclination.to_radians());
+            rotate_z(v, self.longitude_of_node.to_radians())
+        };
+        let [x, y, _] = rotate(position);
+        let [vx, vy, _] = rotate(velocity);
+        ([x, y], [vx, vy])
+    }
+}
+
+impl PlanetarySystem {
+    /// Checks that every belt and ring, the planets' and moons' included, has radii to spread its bodies between.
+    pub fn validate(&self) -> std::io::Result<()> {
+        fn validate_satellites(planets: &[Planet], belts: &[Belt]) -> std::io::Result<()> {
+            for belt in belts {
+                let (inner, outer) = (belt.inner_radius, belt.outer_radius);
+                if !(inner > 0.0 && inner <= outer && outer.is_finite()) {
+                    return Err(invalid_input(&format!("a belt from {inner} to {outer} needs 0 < inner_radius <= outer_radius")));
+                }
+            }
+            for planet in planets {
+                validate_satellites(&planet.moons, &planet.belts)?;
+            }
+            Ok(())
+        }
+        validate_satellites(&self.planets, &self.belts)
+    }
+
+    /// Generates the system with its center of mass at the origin, at rest. The star is the first body.
+    pub fn generate(&self, rng: &mut impl Rng) -> Vec<Body> {
+        let star = Body::new(self.star_mass, [0.0, 0.0], [0.0, 0.0]);
+        let mut bodies = vec![star];
+        add_satellites(&mut bodies, star, &self.planets, &self.belts, rng);
+
+        //shifting every body by the same amount keeps all the orbits as they are
+        let total_mass: f32 = bodies.iter().map(|b| b.mass).sum();
+        if total_mass > 0.0 {
+            let mut center = [0.0f32; 4];
+            for body in &bodies {
+                let weight = body.mass / total_mass;
+                center[0] += body.position[0] * weight;
+                center[1] += body.position[1] * weight;
+                center[2] += body.velocity[0] * weight;
+                center[3] += body.velocity[1] * weight;
+            }
+            for body in &mut bodies {
+                body.position = [body.position[0] - center[0], body.position[1] - center[1]];
+                body.velocity = [body.velocity[0] - center[2], body.velocity[1] - center[3]];
+            }
+        }
+
+        bodies
+    }
+}
+
+fn add_satellites(bodies: &mut Vec<Body>, center: Body, planets: &[Planet], belts: &[Belt], rng: &mut impl Rng) {
+    for planet in planets {
+        let (position, velocity) = planet.orbit.state_vector(center.mass + planet.mass);
+        let body = offset(Body::new(planet.mass, position, velocity), &center);
+        bodies.push(body);
+        add_satellites(bodies, body, &planet.moons, &planet.belts, rng);
+    }
+
+    for belt in belts {
+        belt.add(bodies, &center, rng);
+    }
+}
+
+impl Belt {
+    fn add(&self, bodies: &mut Vec<Body>, center: &Body, rng: &mut impl Rng) {
+        let body_mass = self.mass / self.num_bodies.max(1) as f32;
+        for _ in 0..self.num_bodies {
+            let orbit = OrbitalElements {
+                semi_major_axis: rng.gen_range(self.inner_radius..=self.outer_radius),
+                eccentricity: sample_rayleigh(self.eccentricity_dispersion, rng),
+                inclination: sample_rayleigh(self.inclination_dispersion, rng),
+                longitude_of_node: rng.gen_range(0.0..360.0),
+                argument_of_periapsis: rng.gen_range(0.0..360.0),
+                mean_anomaly: rng.gen_range(0.0..360.0),
+            };
+            let (position, velocity) = orbit.state_vector(center.mass + body_mass);
+            bodies.push(offset(Body::new(body_mass, position, velocity), center));
+        }
+
+        if self.shepherd_mass > 0.0 {
+            for radius in [self.inner_radius * (1.0 - SHEPHERD_GAP), self.outer_radius * (1.0 + SHEPHERD_GAP)] {
+                let orbit = OrbitalElements {
+                    semi_major_axis: radius,
+                    mean_anomaly: rng.gen_range(0.0..360.0),
+                    ..Default::default()
+                };
+                let (position, velocity) = orbit.state_vector(center.mass + self.shepherd_mass);
+                bodies.push(offset(Body::new(self.shepherd_mass, position, velocity), center));
+            }
+        }
+    }
+}
+
+//moves a body given relative to center into center's frame
+fn offset(body: Body, center: &Body) -> Body {
+    Body::new(
+        body.mass,
+        [body.position[0] + center.position[0], body.position[1] + center.position[1]],
+        [body.velocity[0] + center.velocity[0], body.velocity[1] + center.velocity[1]],
+    )
+}
+
+fn sample_rayleigh(scale: f32, rng: &mut impl Rng) -> f32 {
+    if scale <= 0.0 {
+        return 0.0;
+    }
+    let u: f32 = rng.gen_range(f32::EPSILON..1.0);
+    scale * (-2.0 * u.ln()).sqrt()
+}
+
+fn rotate_z([x, y, z]: [f32; 3], angle: f32) -> [f32; 3] {
+    let (sin, cos) = angle.sin_cos();
+    [x * cos - y * sin, x * sin + y * cos, z]
+}
+
+fn rotate_x([x, y, z]: [f32; 3], angle: f32) -> [f32; 3] {
+    let (sin, cos) = angle.sin_cos();
+    [x, y * cos - z * sin, y * sin + z * cos]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn belt(inner_radius: f32, outer_radius: f32) -> Belt {
+        Belt {
+            num_bodies: 10,
+            mass: 0.0,
+            inner_radius,
+            outer_radius,
+            eccentricity_dispersion: 0.0,
+            inclination_dispersion: 0.0,
+            shepherd_mass: 0.0,
+        }
+    }
+
+    #[test]
+    fn belts_need_an_inner_radius_inside_the_outer_one() {
+        let system = |ring: Belt| PlanetarySystem {
+            star_mass: 1.0,
+            planets: vec![Planet {
+                mass: 1e-3,
+                orbit: OrbitalElements { semi_major_axis: 5.0, ..Default::default() },
+                moons: Vec::new(),
+                belts: vec![ring],
+            }],
+            belts: vec![belt(2.0, 3.0)],
+        };
+        assert!(system(belt(0.1, 0.2)).validate().is_ok());
+        assert!(system(belt(0.1, 0.1)).validate().is_ok());
+        for ring in [belt(0.2, 0.1), belt(0.0, 0.1), belt(0.1, f32::INFINITY)] {
+            assert_eq!(system(ring).validate().unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//frames leave the gpu through a ring of readback buffers, so rendering never waits on the previous frame's copy.
+//once a buffer is mapped it goes to a tokio blocking worker that repacks the rows, unmaps the buffer and hands the
+//frame to the frame sink. a frame can only be rendered into a buffer that is free again, which bounds the memory in
+//use and makes the simulation wait when encoding falls behind.
+
+use std::io;
+use std::sync::{Arc, OnceLock};
+
+use tokio::task::JoinHandle;
+use wgpu::{Buffer, BufferAddress, BufferAsyncError, Device, Maintain};
+
+use crate::frames::FrameInfo;
+use crate::sink::{FrameOutput, FrameSink};
+use crate::{HEIGHT, PADDED_BYTES_PER_ROW, UNPADDED_BYTES_PER_ROW, WIDTH};
+
+//frames that can be on their way out at the same time
+pub const READBACK_RING_SIZE: usize = 3;
+
+enum Slot {
+    Free,
+    //being copied into on the gpu, waiting to get mapped
+    Mapping {
+        info: FrameInfo,
+        sequence: u64,
+        mapped: Arc<OnceLock<Result<(), BufferAsyncError>>>,
+    },
+    //handed to a worker, which unmaps it when done and hands back how writing the frame went
+    Encoding(JoinHandle<io::Result<()>>),
+}
+
+pub struct FrameReadback {
+    buffers: Vec<Arc<Buffer>>,
+    slots: Vec<Slot>,
+    //the slot the next frame goes into
+    next: usize,
+    sink: Arc<FrameSink>,
+    //frames submitted so far
+    sequence: u64,
+}
+
+impl FrameReadback {
+    pub fn new(device: &Device) -> Self {
+        let buffers = (0..READBACK_RING_SIZE).map(|_| {
+            Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+                size: (PADDED_BYTES_PER_ROW * HEIGHT) as BufferAddress,
+                usage: wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::MAP_READ,
+                label: Some("Frame readback buffer"),
+                mapped_at_creation: false,
+            }))
+        }).collect();
+
+        Self {
+            buffers,
+            slots: (0..READBACK_RING_SIZE).map(|_| Slot::Free).collect(),
+            next: 0,
+            sink: Arc::new(FrameSink::open(&FrameOutput::default(), WIDTH, HEIGHT).unwrap()),
+            sequence: 0,
+        }
+    }
+
+    /// Sends the frames from now on to `sink`, after writing out the ones still queued.
+    pub async fn set_sink(&mut self, sink: FrameSink, device: &Device) -> io::Result<()> {
+        self.flush(device).await?;
+        self.sink = Arc::new(sink);
+        self.sequence = 0;
+        Ok(())
+    }
+
+    pub fn sink(&self) -> &FrameSink {
+        &self.sink
+    }
+
+    /// Waits until the buffer for the next frame is free and returns it. Fails if the frame that was in it couldn't be
+    /// written.
+    pub async fn acquire(&mut self, device: &Device) -> io::Result<&Buffer> {
+        self.wait(self.next, device).await?;
+        Ok(&self.buffers[self.next])
+    }
+
+    /// Queues the frame just copied into the acquired buffer to be written to the sink. Has to come after the copy got
+    /// submitted.
+    pub fn submit(&mut self, info: FrameInfo, device: &Device) {
+        let mapped = Arc::new(OnceLock::new());
+        let callback_mapped = mapped.clone();
+        self.buffers[self.next].slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            //the callback only runs once per map_async, so the cell is always empty here
+            let _ = callback_mapped.set(result);
+        });
+        self.slots[self.next] = Slot::Mapping { info, sequence: self.sequence, mapped };
+        self.sequence += 1;
+        self.next = (self.next + 1) % READBACK_RING_SIZE;
+
+        //hands off whatever earlier frames are ready, without waiting for the gpu
+        device.poll(Maintain::Poll);
+        for index in 0..READBACK_RING_SIZE {
+            if matches!(&self.slots[index], Slot::Mapping { mapped, .. } if mapped.get().is_some()) {
+                self.start_encoding(index);
+            }
+        }
+    }
+
+    /// Waits for every queued frame to be written. All of them are waited for even if one fails, and the first error is
+    /// returned.
+    pub async fn flush(&mut self, device: &Device) -> io::Result<()> {
+        let mut result = Ok(());
+        for index in 0..READBACK_RING_SIZE {
+            let waited = self.wait(index, device).await;
+            result = result.and(waited);
+        }
+        result
+    }
+
+    async fn wait(&mut self, index: usize, device: &Device) -> io::Result<()> {
+        if let Slot::Mapping { mapped, .. } = &self.slots[index] {
+            if mapped.get().is_none() {
+                device.poll(Maintain::Wait);
+            }
+            self.start_encoding(index);
+        }
+        if let Slot::Encoding(worker) = std::mem::replace(&mut self.slots[index], Slot::Free) {
+            worker.await.map_err(io::Error::other)??;
+        }
+        Ok(())
+    }
+
+    fn start_encoding(&mut self, index: usize) {
+        let Slot::Mapping { info, sequence, mapped } = &self.slots[index] else {
+            return;
+        };
+        let (info, sequence) = (*info, *sequence);
+        let map_error = match mapped.get() {
+            Some(Err(e)) => Some(e.to_string()),
+            _ => None,
+        };
+
+        let buffer = self.buffers[index].clone();
+        let sink = self.sink.clone();
+        self.slots[index] = Slot::Encoding(tokio::task::spawn_blocking(move || {
+            if let Some(e) = map_error {
+                return Err(io::Error::other(format!("failed to map frame {}: {e}", info.frame)));
+            }
+            let padded_data = buffer.slice(..).get_mapped_range();
+            let mut data = padded_data
+                .chunks(PADDED_BYTES_PER_ROW as _)
+                .flat_map(|chunk| &chunk[..UNPADDED_BYTES_PER_ROW as _])
+                .copied()
+                .collect::<Vec<_>>();
+            drop(padded_data);
+            buffer.unmap();
+
+            //the frame is cleared to transparent black, but is meant to be seen on black like the jpegs always were
+            for pixel in data.chunks_mut(4) {
+                pixel[3] = u8::MAX;
+            }
+
+            sink.write(sequence, &info, data)
+                .map_err(|e| io::Error::new(e.kind(), format!("failed to write frame {}: {e}", info.frame)))
+        }));
+    }
+}
@@ -1,13 +1,15 @@
-use image::{ImageBuffer, Rgba};
-use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BlendComponent, BlendFactor, BlendOperation, BlendState, Buffer, BufferAddress, Color, ColorTargetState, ColorWrites, CompareFunction, DepthStencilState, Device, FragmentState, ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, include_wgsl, LoadOp, Maintain, Operations, Origin3d, PipelineLayoutDescriptor, Queue, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, ShaderStages, StencilState, StoreOp, Texture, TextureAspect, TextureDescriptor, TextureSampleType, TextureView, TextureViewDescriptor, TextureViewDimension, VertexAttribute, VertexBufferLayout, VertexFormat, VertexState, VertexStepMode};
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BlendComponent, BlendFactor, BlendOperation, BlendState, Buffer, BufferAddress, Color, ColorTargetState, ColorWrites, CompareFunction, DepthStencilState, Device, FragmentState, ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, include_wgsl, LoadOp, Operations, Origin3d, PipelineLayoutDescriptor, Queue, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, ShaderStages, StencilState, StoreOp, Texture, TextureAspect, TextureDescriptor, TextureSampleType, TextureView, TextureViewDescriptor, TextureViewDimension, VertexAttribute, VertexBufferLayout, VertexFormat, VertexState, VertexStepMode};
 use wgpu::TextureFormat::Rgba8Unorm;
-use crate::{HEIGHT, PADDED_BYTES_PER_ROW, UNPADDED_BYTES_PER_ROW, WIDTH};
+use crate::{HEIGHT, PADDED_BYTES_PER_ROW, WIDTH};
 use crate::camera::CameraState;
+use crate::frames::FrameInfo;
+use crate::readback::FrameReadback;
+use crate::scenario::RenderSettings;
 
 pub struct RenderState {
     pub texture_desc: TextureDescriptor<'static>,
     pub texture_view: TextureView,
-    pub output_buffer: Buffer,
+    pub readback: FrameReadback,
     pub texture: Texture,
 
     pub camera_state: CameraState,
@@ -90,16 +92,7 @@ impl RenderState {
         });
 
 
-        let output_buffer_size = (PADDED_BYTES_PER_ROW * HEIGHT) as wgpu::BufferAddress;
-
-        let output_buffer_desc = wgpu::BufferDescriptor {
-            size: output_buffer_size,
-            usage: wgpu::BufferUsages::COPY_DST
-                | wgpu::BufferUsages::MAP_READ,
-            label: None,
-            mapped_at_creation: false,
-        };
-        let output_buffer = device.create_buffer(&output_buffer_desc);
+        let readback = FrameReadback::new(device);
 
         let (camera_state, camera_bind_group_layout) = CameraState::new(device, (WIDTH, HEIGHT));
 
@@ -249,7 +242,7 @@ impl RenderState {
         Self {
             render_pipeline,
 
-            output_buffer,
+            readback,
             texture_view,
             texture_desc,
             texture,
@@ -263,7 +256,21 @@ impl RenderState {
         }
     }
 
-    pub fn render(&mut self, device: &Device, queue: &Queue, input_buffer: &Buffer, input_len: u32) {
+    pub fn apply_settings(&mut self, queue: &Queue, settings: &RenderSettings) {
+        let [x, y] = settings.camera_target;
+        let camera = &mut self.camera_state.camera;
+        camera.eye = (x, y, -settings.camera_distance).into();
+        camera.target = (x, y, 0.0).into();
+        camera.fovy = settings.fovy;
+
+        self.camera_state.update_view_proj();
+        queue.write_buffer(&self.camera_state.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_state.camera_uniform]));
+    }
+
+    /// Renders a frame and queues it to be written to the frame sink in the background.
+    pub async fn render(&mut self, device: &Device, queue: &Queue, input_buffer: &Buffer, input_len: u32, mut info: FrameInfo) -> std::io::Result<()> {
+        let readback_buffer = self.readback.acquire(device).await?;
+
         let mut encoder = device.create_command_encoder(
             &wgpu::CommandEncoderDescriptor { label: None },
         );
@@ -338,7 +345,7 @@ impl RenderState {
             origin: Origin3d::ZERO,
             aspect: TextureAspect::All,
         }, ImageCopyBuffer {
-            buffer: &self.output_buffer,
+            buffer: readback_buffer,
             layout: ImageDataLayout {
                 offset: 0,
                 bytes_per_row: Some(PADDED_BYTES_PER_ROW),
@@ -346,40 +353,13 @@ impl RenderState {
             },
         }, self.texture_desc.size);
 
-        let submission_index = queue.submit(Some(encoder.finish()));
-        device.poll(Maintain::wait_for(submission_index));
-        return;
-    }
-
-    pub async fn save_buffer_to_image(&self, filename: u32, device: &Device) {
-        {
-            let buffer_slice = self.output_buffer.slice(..);
-            let (tx, rx) = futures_intrusive::channel::shared::oneshot_channel();
-            buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
-                tx.send(result).unwrap();
-            });
-            // wait for the GPU to finish
-            device.poll(wgpu::Maintain::Wait);
-
-            match rx.receive().await {
-                Some(Ok(())) => {
-                    let padded_data = buffer_slice.get_mapped_range();
-                    let data = padded_data
-                        .chunks(PADDED_BYTES_PER_ROW as _)
-                        .map(|chunk| &chunk[..UNPADDED_BYTES_PER_ROW as _])
-                        .flatten()
-                        .map(|x| *x)
-                        .collect::<Vec<_>>();
-                    drop(padded_data);
-                    self.output_buffer.unmap();
-
-                    let image_buffer = ImageBuffer::<Rgba<u8>, _>::from_raw(WIDTH, HEIGHT, data).unwrap();
-                    image_buffer.save(format!("output/{:0>5}.jpeg", filename)).unwrap();
-                    // println!("{filename}");
-                }
-                _ => eprintln!("Something went wrong"),
-            }
-        }
+        queue.submit(Some(encoder.finish()));
+        let camera = &self.camera_state.camera;
+        info.camera_eye = camera.eye.into();
+        info.camera_target = camera.target.into();
+        info.fovy = camera.fovy;
+        self.readback.submit(info, device);
+        Ok(())
     }
 }
 
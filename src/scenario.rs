@@ -0,0 +1,515 @@
+use std::path::{Path, PathBuf};
+
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::escape::EscapeSettings;
+use crate::file_io::invalid_input;
+use crate::canonical::{generate_cold_sphere, generate_lattice, generate_rotating_disk, generate_zeldovich_grid, LatticeKind};
+use crate::cluster::{Cluster, ClusterModel};
+use crate::encounter::{incline, place_on_orbits, Orbit};
+use crate::galaxy::DiskGalaxy;
+use crate::image_source::ImageSource;
+use crate::imf::MassFunction;
+use crate::mass_evolution::MassEvolution;
+use crate::particles::load_bodies;
+use crate::planetary::PlanetarySystem;
+use crate::units::UnitSystem;
+use crate::sink::FrameOutput;
+use crate::spiral::{Bar, SpiralDisk};
+use crate::sim::{gen_actual_spir_g, generate_spiral_galaxy, Body, DT, MIN_DISTANCE_SQ};
+
+/// Declarative description of a run: what to generate, how to simulate it and how to render it.
+/// Usually loaded from a TOML file, see `scenarios/` for examples.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scenario {
+    pub simulation: SimulationSettings,
+    pub render: RenderSettings,
+    pub components: Vec<Component>,
+    //puts every component after the first on an orbit around the ones before it, replacing their offsets and
+    //velocities. not used in ensemble mode
+    pub orbits: Vec<Orbit>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimulationSettings {
+    pub seed: u64,
+    pub dt: f32,
+    pub deterministic: bool,
+    //every component becomes its own independent ensemble member
+    pub ensemble: bool,
+    pub escape: Option<EscapeConfig>,
+    pub mass_evolution: Option<MassEvolutionConfig>,
+    //every mass, length and time in the scenario (dt included) is in these units
+    pub units: UnitSystem,
+    //bodies closer than this don't interact
+    pub min_distance: f32,
+}
+
+impl Default for SimulationSettings {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            dt: DT,
+            deterministic: false,
+            ensemble: false,
+            escape: None,
+            mass_evolution: None,
+            units: UnitSystem::Code,
+            min_distance: MIN_DISTANCE_SQ.sqrt(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EscapeConfig {
+    pub radius: f32,
+    #[serde(default = "default_escape_interval")]
+    pub interval: u32,
+    pub log_path: Option<PathBuf>,
+}
+
+fn default_escape_interval() -> u32 {
+    10
+}
+
+impl From<&EscapeConfig> for EscapeSettings {
+    fn from(config: &EscapeConfig) -> Self {
+        Self {
+            radius: config.radius,
+            interval: config.interval,
+            log_path: config.log_path.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MassEvolutionConfig {
+    #[serde(flatten)]
+    pub model: MassModel,
+    //ticks between two mass updates
+    #[serde(default = "default_mass_update_interval")]
+    pub interval: u32,
+}
+
+fn default_mass_update_interval() -> u32 {
+    10
+}
+
+/// The mass evolution models a scenario can use, picked by the `model` key.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "model", rename_all = "snake_case")]
+pub enum MassModel {
+    //[time, fraction of the initial mass left] pairs, sorted by time
+    Table { table: Vec<[f32; 2]> },
+    //stars turning into remnants, needs galactic or planetary units
+    Stellar,
+}
+
+impl MassEvolutionConfig {
+    pub fn validate(&self, units: UnitSystem) -> std::io::Result<()> {
+        match &self.model {
+            MassModel::Table { table } if table.windows(2).any(|pair| pair[1][0] < pair[0][0]) => {
+                Err(invalid_input("the mass evolution table has to be sorted by time"))
+            }
+            MassModel::Stellar if units == UnitSystem::Code => {
+                Err(invalid_input("the stellar mass evolution model needs galactic or planetary units"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn mass_evolution(&self, units: UnitSystem) -> MassEvolution {
+        match &self.model {
+            MassModel::Table { table } => MassEvolution::Table(table.clone()),
+            MassModel::Stellar => MassEvolution::Stellar(units),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderSettings {
+    //how far the camera sits from the simulation plane
+    pub camera_distance: f32,
+    //the point in the simulation plane the camera looks at
+    pub camera_target: [f32; 2],
+    pub fovy: f32,
+    //also write every frame as a VTK point cloud to output/vtk, for ParaView
+    pub vtk: bool,
+    //also write linear surface density maps of every frame as FITS images to output/fits, same framing
+    pub fits: bool,
+    //a file per frame, an animated GIF or APNG, or a Y4M stream for a video encoder
+    pub output: FrameOutput,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            camera_distance: 350.0,
+            camera_target: [0.0, 0.0],
+            fovy: 45.0,
+            vtk: false,
+            fits: false,
+            output: FrameOutput::default(),
+        }
+    }
+}
+
+/// One generated group of bodies, placed and moved as a whole.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Component {
+    #[serde(flatten)]
+    pub generator: Generator,
+    #[serde(default)]
+    pub offset: [f32; 2],
+    //bulk velocity added to every body
+    #[serde(default)]
+    pub velocity: [f32; 2],
+    //counter-clockwise, in degrees
+    #[serde(default)]
+    pub rotation: f32,
+    //tilt of the disk out of the simulation plane, around the x axis before the rotation, in degrees
+    #[serde(default)]
+    pub inclination: f32,
+    //flips the disk over, so it spins the other way
+    #[serde(default)]
+    pub retrograde: bool,
+    //falls back to one derived from the scenario seed and the component's index
+    #[serde(default)]
+    pub seed: Option<u64>,
+    //per-star masses, without one every generator uses its own fixed masses
+    #[serde(default)]
+    pub imf: Option<MassFunction>,
+}
+
+/// The generators a scenario component can use, picked by the `generator` key.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "generator", rename_all = "snake_case")]
+pub enum Generator {
+    //gen_actual_spir_g
+    SpiralArms {
+        center_mass: f32,
+        num_bodies: u32,
+        num_arms: u32,
+        #[serde(default)]
+        clockwise: bool,
+        radius: f32,
+    },
+    //generate_spiral_galaxy
+    SpiralGalaxy {
+        num_bodies: u32,
+        center_mass: f32,
+        radius: f32,
+    },
+    //exponential disk with a bulge and optional halo, in equilibrium
+    DiskGalaxy(DiskGalaxy),
+    //exponential disk with logarithmic spiral arms and an optional bar
+    SpiralDisk(SpiralDisk),
+    Bar(Bar),
+    //star clusters
+    Plummer(Cluster),
+    King {
+        w0: f32,
+        #[serde(flatten)]
+        cluster: Cluster,
+    },
+    Hernquist(Cluster),
+    //textbook test setups
+    ColdSphere {
+        num_bodies: u32,
+        mass: f32,
+        radius: f32,
+    },
+    RotatingDisk {
+        num_bodies: u32,
+        mass: f32,
+        radius: f32,
+        angular_velocity: f32,
+    },
+    Lattice {
+        num_bodies: u32,
+        mass: f32,
+        size: f32,
+        #[serde(default)]
+        kind: LatticeKind,
+    },
+    Zeldovich {
+        grid_size: u32,
+        mass: f32,
+        size: f32,
+        spectral_index: f32,
+        amplitude: f32,
+        #[serde(default)]
+        growth_rate: f32,
+    },
+    //a star (or planet) with planets, moons, belts and rings around it
+    PlanetarySystem(PlanetarySystem),
+    //bodies spread over an image by its brightness
+    Image(ImageSource),
+    //a hand written list of bodies
+    Bodies {
+        bodies: Vec<Body>,
+    },
+    //bodies from a particle dump or any CSV, .npy or .npz file with the same columns
+    Particles {
+        path: PathBuf,
+    },
+}
+
+impl Generator {
+    /// Generates the bodies centered on the origin, at rest. With a mass function the stars get their masses from it,
+    /// central masses and dark matter keep theirs. Fails when a file the generator reads from can't be used.
+    pub fn generate(&self, mass_function: Option<&MassFunction>, rng: &mut impl Rng) -> std::io::Result<Vec<Body>> {
+        let cluster = |cluster: &Cluster, model: ClusterModel, rng: &mut _| match mass_function {
+            Some(mass_function) => {
+                let cluster = Cluster { mass: mass_function.total_mass.unwrap_or(cluster.mass), ..cluster.clone() };
+                let masses = mass_function.sample(cluster.num_bodies as usize, rng);
+                cluster.generate_with_masses(model, &masses, rng)
+            }
+            None => cluster.generate(model, rng),
+        };
+        //for generators where every body is a star
+        let with_mass_function = |mut bodies: Vec<Body>, rng: &mut _| {
+            if let Some(mass_function) = mass_function {
+                mass_function.assign(&mut bodies, rng);
+            }
+            bodies
+        };
+
+        Ok(match self {
+            Generator::SpiralArms { center_mass, num_bodies, num_arms, clockwise, radius } => {
+                let mut bodies = gen_actual_spir_g([0.0, 0.0], [0.0, 0.0], *center_mass, *num_bodies, *num_arms, *clockwise, *radius, rng);
+                if let Some(mass_function) = mass_function {
+                    //the central body comes first
+                    mass_function.assign(&mut bodies[1..], rng);
+                }
+                bodies
+            }
+            Generator::SpiralGalaxy { num_bodies, center_mass, radius } => {
+                let mut bodies = generate_spiral_galaxy([0.0, 0.0], *num_bodies, *center_mass, *radius, rng);
+                if let Some(mass_function) = mass_function {
+                    //the central bodies come last
+                    mass_function.assign(&mut bodies[..*num_bodies as usize], rng);
+                }
+                bodies
+            }
+            Generator::DiskGalaxy(galaxy) => galaxy.generate_with_mass_function(mass_function, rng),
+            Generator::SpiralDisk(disk) => {
+                let mut bodies = disk.generate(rng);
+                if let Some(mass_function) = mass_function {
+                    let stars = if disk.center_mass > 0.0 { 1 } else { 0 };
+                    mass_function.assign(&mut bodies[stars..], rng);
+                }
+                bodies
+            }
+            Generator::Bar(bar) => with_mass_function(bar.generate(rng), rng),
+            Generator::Plummer(c) => cluster(c, ClusterModel::Plummer, rng),
+            Generator::King { w0, cluster: c } => cluster(c, ClusterModel::King { w0: *w0 }, rng),
+            Generator::Hernquist(c) => cluster(c, ClusterModel::Hernquist, rng),
+            Generator::Image(image) => with_mass_function(image.generate(rng)?, rng),
+            Generator::ColdSphere { num_bodies, mass, radius } => {
+                with_mass_function(generate_cold_sphere([0.0, 0.0], *num_bodies, *mass, *radius, rng), rng)
+            }
+            Generator::RotatingDisk { num_bodies, mass, radius, angular_velocity } => {
+                with_mass_function(generate_rotating_disk([0.0, 0.0], *num_bodies, *mass, *radius, *angular_velocity, rng), rng)
+            }
+            Generator::Lattice { num_bodies, mass, size, kind } => {
+                with_mass_function(generate_lattice([0.0, 0.0], *num_bodies, *mass, *size, *kind, rng), rng)
+            }
+            Generator::Zeldovich { grid_size, mass, size, spectral_index, amplitude, growth_rate } => {
+                with_mass_function(generate_zeldovich_grid([0.0, 0.0], *grid_size, *mass, *size, *spectral_index, *amplitude, *growth_rate, rng), rng)
+            }
+            //the masses are given explicitly
+            Generator::PlanetarySystem(system) => system.generate(rng),
+            //hand written masses are left as they are
+            Generator::Bodies { bodies } => bodies.clone(),
+            Generator::Particles { path } => {
+                load_bodies(path).map_err(|e| std::io::Error::new(e.kind(), format!("{}: {e}", path.display())))?
+            }
+        })
+    }
+}
+
+impl Generator {
+    /// Checks the settings that can't be generated from, so they show up when the scenario is loaded.
+    pub fn validate(&self) -> std::io::Result<()> {
+        match self {
+            Generator::Zeldovich { grid_size, .. } if !grid_size.is_power_of_two() => {
+                Err(invalid_input(&format!("the Zel'dovich grid size has to be a power of two, not {grid_size}")))
+            }
+            Generator::PlanetarySystem(system) => system.validate(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the generated velocities come from the generated masses (assuming G = 1), rather than being given.
+    pub fn uses_gravity(&self) -> bool {
+        matches!(
+            self,
+            Generator::DiskGalaxy(_) | Generator::SpiralDisk(_) | Generator::Bar(_) | Generator::Plummer(_)
+                | Generator::King { .. } | Generator::Hernquist(_) | Generator::PlanetarySystem(_)
+        )
+    }
+}
+
+impl Component {
+    /// Generates the component for a simulation with the given gravitational constant.
+    pub fn build(&self, scenario_seed: u64, index: usize, gravitational_constant: f32) -> std::io::Result<Vec<Body>> {
+        let seed = self.seed.unwrap_or_else(|| component_seed(scenario_seed, index));
+        let mut rng = SmallRng::seed_from_u64(seed);
+
+        let mut bodies = self.generator.generate(self.imf.as_ref(), &mut rng)?;
+        if self.generator.uses_gravity() {
+            let scale = gravitational_constant.sqrt();
+            for body in &mut bodies {
+                body.velocity = body.velocity.map(|v| v * scale);
+            }
+        }
+        incline(&mut bodies, self.inclination, self.retrograde);
+        transform(&mut bodies, self.rotation, self.offset, self.velocity);
+        Ok(bodies)
+    }
+}
+
+impl Scenario {
+    pub fn from_file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Self::from_toml(&contents)
+    }
+
+    pub fn from_toml(contents: &str) -> std::io::Result<Self> {
+        let scenario: Self = toml::from_str(contents).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    /// Checks the simulation and every component's settings, the errors say which component they are about.
+    pub fn validate(&self) -> std::io::Result<()> {
+        if let Some(mass_evolution) = &self.simulation.mass_evolution {
+            mass_evolution.validate(self.simulation.units)?;
+        }
+        for (i, component) in self.components.iter().enumerate() {
+            component.generator.validate()
+                .and_then(|_| component.imf.as_ref().map_or(Ok(()), MassFunction::validate))
+                .map_err(|e| std::io::Error::new(e.kind(), format!("component {i}: {e}")))?;
+        }
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).unwrap()
+    }
+
+    /// Generates the bodies of every component, in order, and puts them on their orbits.
+    pub fn build_components(&self) -> std::io::Result<Vec<Vec<Body>>> {
+        self.validate()?;
+        let mut components = self.components.iter().enumerate().map(|(i, component)| {
+            component.build(self.simulation.seed, i, self.simulation.units.gravitational_constant())
+        }).collect::<std::io::Result<Vec<_>>>()?;
+
+        if !self.simulation.ensemble {
+            place_on_orbits(&mut components, &self.orbits, self.simulation.units.gravitational_constant());
+        }
+        Ok(components)
+    }
+}
+
+//the files live in scenarios/presets
+const PRESETS: [(&str, &str); 5] = [
+    ("antennae", include_str!("../scenarios/presets/antennae.toml")),
+    ("mice", include_str!("../scenarios/presets/mice.toml")),
+    ("cartwheel", include_str!("../scenarios/presets/cartwheel.toml")),
+    ("minor_merger", include_str!("../scenarios/presets/minor_merger.toml")),
+    ("isolated_disk", include_str!("../scenarios/presets/isolated_disk.toml")),
+];
+
+impl Scenario {
+    /// One of the built in scenarios, see `preset_names`.
+    pub fn preset(name: &str) -> Option<Self> {
+        PRESETS.iter().find(|(preset, _)| *preset == name).map(|(_, contents)| Self::from_toml(contents).unwrap())
+    }
+
+    pub fn preset_names() -> impl Iterator<Item = &'static str> {
+        PRESETS.iter().map(|(name, _)| *name)
+    }
+}
+
+/// Rotates bodies around the origin, then shifts them by `offset` and adds `velocity` to all of them.
+pub fn transform(bodies: &mut [Body], rotation: f32, offset: [f32; 2], velocity: [f32; 2]) {
+    let (sin, cos) = rotation.to_radians().sin_cos();
+    let rotate = |[x, y]: [f32; 2]| [x * cos - y * sin, x * sin + y * cos];
+
+    for body in bodies {
+        let [x, y] = rotate(body.position);
+        let [vx, vy] = rotate(body.velocity);
+        body.position = [x + offset[0], y + offset[1]];
+        body.velocity = [vx + velocity[0], vy + velocity[1]];
+    }
+}
+
+fn component_seed(scenario_seed: u64, index: usize) -> u64 {
+    scenario_seed ^ (index as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_parse() {
+        for name in Scenario::preset_names() {
+            //a preset that doesn't parse panics in here
+            let scenario = Scenario::preset(name).unwrap_or_else(|| panic!("preset {name} isn't found by name"));
+            scenario.build_components().unwrap_or_else(|e| panic!("preset {name}: {e}"));
+        }
+    }
+
+    #[test]
+    fn scenario_files_parse() {
+        let mut directories = vec![Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios")];
+        let mut count = 0;
+        while let Some(directory) = directories.pop() {
+            for entry in std::fs::read_dir(directory).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    directories.push(path);
+                } else if path.extension().is_some_and(|e| e == "toml") {
+                    Scenario::from_file(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+                    count += 1;
+                }
+            }
+        }
+        assert!(count > 0);
+    }
+
+    #[test]
+    fn bad_settings_are_rejected_when_loading() {
+        let zeldovich = |grid_size: u32| format!(r#"
+            [[components]]
+            generator = "zeldovich"
+            grid_size = {grid_size}
+            mass = 1.0
+            size = 10.0
+            spectral_index = -1.0
+            amplitude = 0.1
+        "#);
+        assert!(Scenario::from_toml(&zeldovich(16)).is_ok());
+        for grid_size in [0, 12] {
+            assert_eq!(Scenario::from_toml(&zeldovich(grid_size)).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        }
+
+        let imf = |min_mass: f32, max_mass: f32| format!("{}imf = {{ imf = \"kroupa\", min_mass = {min_mass}, max_mass = {max_mass} }}", zeldovich(16));
+        assert!(Scenario::from_toml(&imf(0.1, 10.0)).is_ok());
+        assert_eq!(Scenario::from_toml(&imf(10.0, 0.1)).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+
+        let mass_evolution = |units: &str, model: &str| format!("[simulation]\nunits = \"{units}\"\n[simulation.mass_evolution]\n{model}\n");
+        assert!(Scenario::from_toml(&mass_evolution("galactic", "model = \"stellar\"")).is_ok());
+        assert!(Scenario::from_toml(&mass_evolution("code", "model = \"stellar\"")).is_err());
+        assert!(Scenario::from_toml(&mass_evolution("code", "model = \"table\"\ntable = [[0.0, 1.0], [5.0, 0.5]]")).is_ok());
+        assert!(Scenario::from_toml(&mass_evolution("code", "model = \"table\"\ntable = [[5.0, 1.0], [0.0, 0.5]]")).is_err());
+    }
+}
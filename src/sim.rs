@@ -1,28 +1,198 @@
+use std::collections::HashSet;
 use std::f32::consts::PI;
 
 use rand::distributions::Distribution;
+use rand::rngs::SmallRng;
 use rand::{Rng, SeedableRng};
 use rand_distr::Normal;
-use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages, ComputePipeline, ComputePipelineDescriptor, Device, include_wgsl, Maintain, PipelineLayoutDescriptor, Queue, ShaderStages};
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, Buffer, BufferAddress, BufferBindingType, BufferDescriptor, BufferUsages, ComputePipeline, ComputePipelineDescriptor, Device, include_wgsl, Maintain, PipelineLayoutDescriptor, Queue, ShaderStages};
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use crate::diagnostics::Diagnostics;
+use crate::mass_evolution::MassEvolution;
+use crate::scenario::Scenario;
+use crate::units::UnitSystem;
+use crate::escape::{EscapeDetector, EscapeSettings, ESCAPER_LIST_HEADER, ESCAPER_SIZE};
 
+const WORKGROUP_SIZE: u32 = 256;
+//body buffers never get smaller than this, so there is always something to bind
+const MIN_CAPACITY: u32 = 256;
 
-pub struct SimState {
+const VEC2_SIZE: BufferAddress = std::mem::size_of::<[f32; 2]>() as BufferAddress;
+const SCALAR_SIZE: BufferAddress = std::mem::size_of::<u32>() as BufferAddress;
+
+pub const DT: f32 = 0.01;
+//pairs closer than this (squared) don't interact at all, instead of flinging each other apart
+pub const MIN_DISTANCE_SQ: f32 = 0.3;
+
+//uniform shared by the simulation and compaction shaders
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SimParams {
+    pub num_bodies: u32,
+    pub dt: f32,
+    pub escape_radius: f32,
+    pub gravitational_constant: f32,
+    pub min_distance_sq: f32,
+    pub _padding: [u32; 3],
+}
+
+//per-body gpu storage. all of it is sized for `capacity` bodies, only the first `num_bodies` are alive.
+pub struct BodyBuffers {
     pub input_masses: Buffer,
+    //masses the bodies were added with, the mass evolution model works from these
+    pub initial_masses: Buffer,
     pub positions_buffer: Buffer,
+    pub velocities_buffer: Buffer,
+    pub ids_buffer: Buffer,
+    //1 for alive bodies, 0 for bodies waiting to be compacted away
+    pub flags_buffer: Buffer,
+    //written by the simulation shader every tick
+    pub potentials_buffer: Buffer,
+    //index of the ensemble member every body belongs to
+    pub systems_buffer: Buffer,
+    //species tag of every body, see `Body::species`
+    pub species_buffer: Buffer,
+    //[start, end) body range of every ensemble member
+    pub ranges_buffer: Buffer,
 
     pub output_positions: Buffer,
 
+    //compaction working memory
+    pub destinations_buffer: Buffer,
+    pub scratch_buffer: Buffer,
+
+    pub escapers_buffer: Buffer,
+
+    pub capacity: u32,
+}
+
+impl BodyBuffers {
+    pub fn new(device: &Device, capacity: u32) -> Self {
+        let capacity = capacity.max(MIN_CAPACITY);
+        let vec_size = capacity as BufferAddress * VEC2_SIZE;
+        let scalar_size = capacity as BufferAddress * SCALAR_SIZE;
+        let attribute_usage = BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST;
+
+        let create = |label: &str, size: BufferAddress, usage: BufferUsages| {
+            device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+
+        Self {
+            input_masses: create("Body masses input buffer", scalar_size, attribute_usage),
+            initial_masses: create("Body initial masses buffer", scalar_size, attribute_usage),
+            positions_buffer: create("GPU positions buffer", vec_size, attribute_usage),
+            velocities_buffer: create("GPU-only velocities buffer", vec_size, attribute_usage),
+            ids_buffer: create("Body ids buffer", scalar_size, attribute_usage),
+            flags_buffer: create("Body flags buffer", scalar_size, attribute_usage),
+            potentials_buffer: create("Body potentials buffer", scalar_size, attribute_usage),
+            systems_buffer: create("Body systems buffer", scalar_size, attribute_usage),
+            species_buffer: create("Body species buffer", scalar_size, attribute_usage),
+            //every member needs at least one body when it is added, so `capacity` ranges are always enough
+            ranges_buffer: create("System ranges buffer", vec_size, attribute_usage),
+
+            output_positions: create("Output positions buffer", vec_size, BufferUsages::COPY_DST | BufferUsages::VERTEX),
+
+            destinations_buffer: create("Compaction destinations buffer", scalar_size, BufferUsages::STORAGE),
+            scratch_buffer: create("Compaction scratch buffer", vec_size, BufferUsages::STORAGE | BufferUsages::COPY_SRC),
+
+            escapers_buffer: create("Escaper list buffer", ESCAPER_LIST_HEADER + capacity as BufferAddress * ESCAPER_SIZE, attribute_usage),
+
+            capacity,
+        }
+    }
+
+    //position of the initial masses in `attributes`
+    pub(crate) const INITIAL_MASSES_ATTRIBUTE: usize = 3;
+
+    //element size of every attribute, in `attributes` order
+    pub(crate) const ATTRIBUTE_SIZES: [BufferAddress; 9] = [
+        VEC2_SIZE,
+        VEC2_SIZE,
+        SCALAR_SIZE,
+        SCALAR_SIZE,
+        SCALAR_SIZE,
+        SCALAR_SIZE,
+        SCALAR_SIZE,
+        SCALAR_SIZE,
+        SCALAR_SIZE,
+    ];
+
+    //buffers that get moved around by compaction and carried over when growing, with their element size
+    pub(crate) fn attributes(&self) -> [(&Buffer, BufferAddress); 9] {
+        let buffers = [
+            &self.positions_buffer,
+            &self.velocities_buffer,
+            &self.input_masses,
+            &self.initial_masses,
+            &self.ids_buffer,
+            &self.flags_buffer,
+            &self.potentials_buffer,
+            &self.systems_buffer,
+            &self.species_buffer,
+        ];
+        std::array::from_fn(|i| (buffers[i], Self::ATTRIBUTE_SIZES[i]))
+    }
+}
+
+pub struct SimState {
+    pub buffers: BodyBuffers,
+
+    pub params: SimParams,
+    pub params_buffer: Buffer,
+    pub count_buffer: Buffer,
+    pub next_id: u32,
+
+    //cpu copy of the ranges buffer, one entry per ensemble member
+    pub system_ranges: Vec<[u32; 2]>,
+
+    pub time: f32,
+    pub tick_count: u64,
+
+    pub mass_evolution: Option<MassEvolution>,
+    //how many ticks between two mass updates
+    pub mass_update_interval: u32,
+    //copy of the initial masses of the live bodies, so mass updates don't have to read them back
+    initial_masses: Vec<f32>,
+
+    pub seed: u64,
+    //steps with separate kick and drift dispatches, so reruns are bit-identical
+    pub deterministic: bool,
+
+    //what the numbers in the buffers mean, see `set_units`
+    pub units: UnitSystem,
+
     pub compute_pipeline: ComputePipeline,
-    pub bodies: Vec<Body>,
+    pub kick_pipeline: ComputePipeline,
+    pub drift_pipeline: ComputePipeline,
+    pub input_bind_group_layout: BindGroupLayout,
     pub input_bind_group: BindGroup,
+
+    pub scan_pipeline: ComputePipeline,
+    pub scatter_scalar_pipeline: ComputePipeline,
+    pub scatter_vec_pipeline: ComputePipeline,
+    pub compact_bind_group_layout: BindGroupLayout,
+    pub scatter_scalar_bind_group_layout: BindGroupLayout,
+    pub scatter_vec_bind_group_layout: BindGroupLayout,
+    pub compact_bind_group: BindGroup,
+    pub rebuild_ranges_pipeline: ComputePipeline,
+    pub ranges_bind_group_layout: BindGroupLayout,
+    pub ranges_bind_group: BindGroup,
+
+    pub escape: EscapeDetector,
 }
 
 impl SimState {
-    pub fn new(device: &Device) -> Self {
-        let mut bodies: Vec<Body> = gen_actual_spir_g([0.0, 32.5], [2.0, 6.0] ,50_000.0, 10_000, 2, true, 35.0);
+    pub fn new(device: &Device, queue: &Queue, seed: u64) -> Self {
+        let mut rng = SmallRng::seed_from_u64(seed);
+
+        let mut bodies: Vec<Body> = gen_actual_spir_g([0.0, 32.5], [2.0, 6.0] ,50_000.0, 10_000, 2, true, 35.0, &mut rng);
 
-        let mut bodies_1: Vec<Body> = gen_actual_spir_g([0.0, -32.5], [-2.0, -6.0] ,50_000.0, 10_000, 2, true, 35.0);
+        let mut bodies_1: Vec<Body> = gen_actual_spir_g([0.0, -32.5], [-2.0, -6.0] ,50_000.0, 10_000, 2, true, 35.0, &mut rng);
 
         bodies.append(&mut bodies_1);
 
@@ -32,43 +202,75 @@ impl SimState {
         //     Body::new(1000.0, [3.0, 0.0], [0.0, 5.0]),
         // ];
 
-        let masses = bodies.iter().map(|b| {
-            b.mass
-        }).collect::<Vec<_>>();
-        let positions = bodies.iter().map(|b| {
-            b.position
-        }).collect::<Vec<_>>();
-        let velocities = bodies.iter().map(|b| {
-            b.velocity
-        }).collect::<Vec<_>>();
+        let mut sim_state = Self::empty(device, bodies.len() as u32);
+        sim_state.seed = seed;
+        sim_state.add_system(device, queue, &bodies);
 
+        sim_state
+    }
 
-        let input_masses = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Body masses input buffer"),
-            contents: bytemuck::cast_slice(masses.as_slice()),
-            usage: wgpu::BufferUsages::STORAGE,
-        });
+    /// Generates every component of the scenario and applies its simulation settings.
+    pub fn from_scenario(device: &Device, queue: &Queue, scenario: &Scenario) -> std::io::Result<Self> {
+        let members = scenario.build_components()?;
+        let settings = &scenario.simulation;
 
-        let positions_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("GPU positions buffer"),
-            contents: bytemuck::cast_slice(positions.as_slice()),
-            usage: wgpu::BufferUsages::STORAGE
-                | wgpu::BufferUsages::COPY_SRC,
-            // | wgpu::BufferUsages::VERTEX,
-        });
+        let mut sim_state = if settings.ensemble {
+            Self::new_ensemble(device, queue, &members)
+        } else {
+            let bodies = members.concat();
+            let mut sim_state = Self::empty(device, bodies.len() as u32);
+            sim_state.add_system(device, queue, &bodies);
+            sim_state
+        };
 
-        //todo initial velocities can be set through this buffer (unimplemented)
-        let velocities_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("GPU-only velocities buffer"),
-            contents: bytemuck::cast_slice(velocities.as_slice()),
-            usage: wgpu::BufferUsages::STORAGE,
-        });
+        sim_state.seed = settings.seed;
+        sim_state.deterministic = settings.deterministic;
+        sim_state.set_dt(queue, settings.dt);
+        sim_state.set_units(queue, settings.units);
+        sim_state.set_min_distance(queue, settings.min_distance);
+        sim_state.set_escape_settings(queue, settings.escape.as_ref().map(EscapeSettings::from))?;
+        if let Some(config) = &settings.mass_evolution {
+            sim_state.set_mass_evolution(Some(config.mass_evolution(settings.units)), config.interval);
+        }
+
+        Ok(sim_state)
+    }
+
+    /// Packs many independent systems into the same buffers. Bodies only interact with the bodies of their own member,
+    /// so all of them get stepped by a single dispatch.
+    pub fn new_ensemble(device: &Device, queue: &Queue, members: &[Vec<Body>]) -> Self {
+        let capacity = members.iter().map(|m| m.len() as u32).sum();
+        let mut sim_state = Self::empty(device, capacity);
+        for member in members {
+            sim_state.add_system(device, queue, member);
+        }
+
+        sim_state
+    }
 
+    /// Creates a simulation without any bodies, with room for `capacity` of them before the buffers have to grow.
+    pub fn empty(device: &Device, capacity: u32) -> Self {
+        let buffers = BodyBuffers::new(device, capacity);
 
-        let output_positions = device.create_buffer(&BufferDescriptor {
-            label: Some("Output positions buffer"),
-            size: positions_buffer.size(),
-            usage: wgpu::BufferUsages::COPY_DST | BufferUsages::VERTEX,
+        let params = SimParams {
+            num_bodies: 0,
+            dt: DT,
+            escape_radius: 0.0,
+            gravitational_constant: 1.0,
+            min_distance_sq: MIN_DISTANCE_SQ,
+            _padding: [0; 3],
+        };
+
+        let params_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Simulation params buffer"),
+            contents: bytemuck::cast_slice(&[params]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let count_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Active body count buffer"),
+            size: SCALAR_SIZE,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
             mapped_at_creation: false,
         });
 
@@ -76,57 +278,19 @@ impl SimState {
             label: Some("Input bind group layout"),
             entries: &[
                 //positions
-                BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
+                storage_layout_entry(0, false),
                 //masses
-                BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
+                storage_layout_entry(1, true),
                 //the velocities buffer
-                BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }
-            ],
-        });
-
-        let input_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: Some("Input Bind Group"),
-            layout: &input_bind_group_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: positions_buffer.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: input_masses.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 2,
-                    resource: velocities_buffer.as_entire_binding(),
-                }
+                storage_layout_entry(2, false),
+                //params
+                uniform_layout_entry(3),
+                //potentials
+                storage_layout_entry(4, false),
+                //systems
+                storage_layout_entry(5, true),
+                //ranges
+                storage_layout_entry(6, true),
             ],
         });
 
@@ -147,19 +311,224 @@ impl SimState {
             entry_point: "main",
         });
 
+        let kick_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Simulation kick pipeline"),
+            layout: Some(&compute_pipeline_layout),
+            module: &shader,
+            entry_point: "kick",
+        });
+
+        let drift_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Simulation drift pipeline"),
+            layout: Some(&compute_pipeline_layout),
+            module: &shader,
+            entry_point: "drift",
+        });
+
+        let compact_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Compaction bind group layout"),
+            entries: &[
+                //params
+                uniform_layout_entry(0),
+                //flags
+                storage_layout_entry(1, true),
+                //destinations
+                storage_layout_entry(2, false),
+                //active count
+                storage_layout_entry(3, false),
+            ],
+        });
+
+        let scatter_scalar_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Scalar scatter bind group layout"),
+            entries: &[
+                storage_layout_entry(0, true),
+                storage_layout_entry(1, false),
+            ],
+        });
+
+        let scatter_vec_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Vector scatter bind group layout"),
+            entries: &[
+                storage_layout_entry(2, true),
+                storage_layout_entry(3, false),
+            ],
+        });
+
+        let compact_shader = device.create_shader_module(include_wgsl!("compact_shader.wgsl"));
+
+        let scan_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Compaction scan pipeline layout"),
+            bind_group_layouts: &[
+                &compact_bind_group_layout
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let scan_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Compaction scan pipeline"),
+            layout: Some(&scan_pipeline_layout),
+            module: &compact_shader,
+            entry_point: "scan",
+        });
+
+        let scatter_scalar_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Scalar scatter pipeline layout"),
+            bind_group_layouts: &[
+                &compact_bind_group_layout,
+                &scatter_scalar_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let scatter_scalar_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Scalar scatter pipeline"),
+            layout: Some(&scatter_scalar_pipeline_layout),
+            module: &compact_shader,
+            entry_point: "scatter_scalar",
+        });
+
+        let scatter_vec_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Vector scatter pipeline layout"),
+            bind_group_layouts: &[
+                &compact_bind_group_layout,
+                &scatter_vec_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let scatter_vec_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Vector scatter pipeline"),
+            layout: Some(&scatter_vec_pipeline_layout),
+            module: &compact_shader,
+            entry_point: "scatter_vec",
+        });
+
+        let ranges_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Ranges bind group layout"),
+            entries: &[
+                //systems
+                storage_layout_entry(4, true),
+                //ranges
+                storage_layout_entry(5, false),
+            ],
+        });
+
+        let rebuild_ranges_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Rebuild ranges pipeline layout"),
+            bind_group_layouts: &[
+                &compact_bind_group_layout,
+                &ranges_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let rebuild_ranges_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Rebuild ranges pipeline"),
+            layout: Some(&rebuild_ranges_pipeline_layout),
+            module: &compact_shader,
+            entry_point: "rebuild_ranges",
+        });
+
+        let input_bind_group = create_input_bind_group(device, &input_bind_group_layout, &buffers, &params_buffer);
+        let compact_bind_group = create_compact_bind_group(device, &compact_bind_group_layout, &buffers, &params_buffer, &count_buffer);
+        let ranges_bind_group = create_ranges_bind_group(device, &ranges_bind_group_layout, &buffers);
+
+        let escape = EscapeDetector::new(device, &buffers, &params_buffer);
+
         Self {
-            bodies,
+            buffers,
 
-            input_masses,
-            positions_buffer,
-            output_positions,
+            params,
+            params_buffer,
+            count_buffer,
+            next_id: 0,
+
+            system_ranges: Vec::new(),
+
+            time: 0.0,
+            tick_count: 0,
+
+            mass_evolution: None,
+            mass_update_interval: 1,
+            initial_masses: Vec::new(),
+
+            seed: 0,
+            deterministic: false,
+
+            units: UnitSystem::Code,
 
-            input_bind_group,
             compute_pipeline,
+            kick_pipeline,
+            drift_pipeline,
+            input_bind_group_layout,
+            input_bind_group,
+
+            scan_pipeline,
+            scatter_scalar_pipeline,
+            scatter_vec_pipeline,
+            compact_bind_group_layout,
+            scatter_scalar_bind_group_layout,
+            scatter_vec_bind_group_layout,
+            compact_bind_group,
+            rebuild_ranges_pipeline,
+            ranges_bind_group_layout,
+            ranges_bind_group,
+
+            escape,
         }
     }
 
-    pub async fn tick(&mut self, device: &Device, queue: &Queue) { // -> Vec<[f32;2]> {
+    pub fn num_bodies(&self) -> u32 {
+        self.params.num_bodies
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.buffers.capacity
+    }
+
+    pub fn num_systems(&self) -> u32 {
+        self.system_ranges.len() as u32
+    }
+
+    pub fn set_dt(&mut self, queue: &Queue, dt: f32) {
+        self.params.dt = dt;
+        self.write_params(queue);
+    }
+
+    /// Switches the gravitational constant to the one of `units`. Bodies already in the simulation keep their numbers.
+    pub fn set_units(&mut self, queue: &Queue, units: UnitSystem) {
+        self.units = units;
+        self.params.gravitational_constant = units.gravitational_constant();
+        self.write_params(queue);
+    }
+
+    /// Bodies closer than `min_distance` to each other don't interact.
+    pub fn set_min_distance(&mut self, queue: &Queue, min_distance: f32) {
+        self.params.min_distance_sq = min_distance * min_distance;
+        self.write_params(queue);
+    }
+
+    /// Enables (or with `None` disables) automatic removal of escaping bodies.
+    pub fn set_escape_settings(&mut self, queue: &Queue, settings: Option<EscapeSettings>) -> std::io::Result<()> {
+        self.params.escape_radius = settings.as_ref().map_or(0.0, |s| s.radius);
+        self.write_params(queue);
+        self.escape.set_settings(settings)
+    }
+
+    /// Steps the simulation once. Fails when escapers were removed but couldn't be logged, the step itself still happened.
+    pub async fn tick(&mut self, device: &Device, queue: &Queue) -> std::io::Result<()> {
+        if self.tick_count.is_multiple_of(self.mass_update_interval.max(1) as u64) && self.params.num_bodies > 0 {
+            self.update_masses(queue);
+        }
+
+        self.time += self.params.dt;
+        self.tick_count += 1;
+
+        if self.params.num_bodies == 0 {
+            return Ok(());
+        }
+
         let mut encoder = device.create_command_encoder(
             &wgpu::CommandEncoderDescriptor { label: None },
         );
@@ -168,26 +537,488 @@ impl SimState {
             let mut compute_pass = encoder.begin_compute_pass(&Default::default());
 
             compute_pass.set_bind_group(0, &self.input_bind_group, &[]);
-            compute_pass.set_pipeline(&self.compute_pipeline);
             //todo figure out better workgroup count
-            compute_pass.dispatch_workgroups((self.bodies.len() as f32 / 256.0).ceil() as u32, 1, 1);
+            if self.deterministic {
+                compute_pass.set_pipeline(&self.kick_pipeline);
+                compute_pass.dispatch_workgroups(self.workgroup_count(), 1, 1);
+                compute_pass.set_pipeline(&self.drift_pipeline);
+                compute_pass.dispatch_workgroups(self.workgroup_count(), 1, 1);
+            } else {
+                compute_pass.set_pipeline(&self.compute_pipeline);
+                compute_pass.dispatch_workgroups(self.workgroup_count(), 1, 1);
+            }
 
             drop(compute_pass);
         }
-        encoder.copy_buffer_to_buffer(&self.positions_buffer, 0, &self.output_positions, 0, self.positions_buffer.size());
+        queue.submit(Some(encoder.finish()));
+
+        //escapers are judged by the potentials the step just computed, which are only valid once a body has been
+        //stepped, and dropped before the positions are handed to the renderer
+        let escaped = if self.escape.is_due(self.tick_count) {
+            self.remove_escapers(device, queue).await
+        } else {
+            Ok(())
+        };
+
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor { label: Some("Output positions encoder") },
+        );
+        encoder.copy_buffer_to_buffer(&self.buffers.positions_buffer, 0, &self.buffers.output_positions, 0, self.params.num_bodies as BufferAddress * VEC2_SIZE);
 
         let sub_index = queue.submit(Some(encoder.finish()));
         device.poll(Maintain::WaitForSubmissionIndex(sub_index));
+        escaped
+    }
+
+    /// Appends bodies to the last system in the simulation, growing the buffers if needed.
+    /// Returns the ids given to the new bodies.
+    pub fn add_bodies(&mut self, device: &Device, queue: &Queue, bodies: &[Body]) -> Vec<u32> {
+        if self.system_ranges.is_empty() {
+            return self.add_system(device, queue, bodies).1;
+        }
+        let system = self.num_systems() - 1;
+        self.push_bodies(device, queue, system, bodies)
+    }
+
+    /// Adds a new independent ensemble member. Returns its index and the ids given to its bodies.
+    pub fn add_system(&mut self, device: &Device, queue: &Queue, bodies: &[Body]) -> (u32, Vec<u32>) {
+        let system = self.num_systems();
+        let start = self.params.num_bodies;
+        self.system_ranges.push([start, start]);
+        (system, self.push_bodies(device, queue, system, bodies))
+    }
+
+    //bodies always go to the end of the buffers, so `system` has to be the last member to keep the ranges contiguous
+    fn push_bodies(&mut self, device: &Device, queue: &Queue, system: u32, bodies: &[Body]) -> Vec<u32> {
+        let start = self.params.num_bodies;
+        let end = start + bodies.len() as u32;
+        if end > self.buffers.capacity || self.num_systems() > self.buffers.capacity {
+            self.grow(device, queue, end.max(self.num_systems()));
+        }
+
+        let ids = (self.next_id..self.next_id + bodies.len() as u32).collect::<Vec<_>>();
+        self.next_id += bodies.len() as u32;
+
+        let masses = bodies.iter().map(|b| {
+            b.mass
+        }).collect::<Vec<_>>();
+        let positions = bodies.iter().map(|b| {
+            b.position
+        }).collect::<Vec<_>>();
+        let velocities = bodies.iter().map(|b| {
+            b.velocity
+        }).collect::<Vec<_>>();
+        let species = bodies.iter().map(|b| {
+            b.species
+        }).collect::<Vec<_>>();
+        let flags = vec![1u32; bodies.len()];
+        let systems = vec![system; bodies.len()];
+
+        let vec_offset = start as BufferAddress * VEC2_SIZE;
+        let scalar_offset = start as BufferAddress * SCALAR_SIZE;
+        queue.write_buffer(&self.buffers.positions_buffer, vec_offset, bytemuck::cast_slice(&positions));
+        queue.write_buffer(&self.buffers.velocities_buffer, vec_offset, bytemuck::cast_slice(&velocities));
+        queue.write_buffer(&self.buffers.input_masses, scalar_offset, bytemuck::cast_slice(&masses));
+        queue.write_buffer(&self.buffers.initial_masses, scalar_offset, bytemuck::cast_slice(&masses));
+        self.initial_masses.extend(&masses);
+        queue.write_buffer(&self.buffers.ids_buffer, scalar_offset, bytemuck::cast_slice(&ids));
+        queue.write_buffer(&self.buffers.flags_buffer, scalar_offset, bytemuck::cast_slice(&flags));
+        queue.write_buffer(&self.buffers.systems_buffer, scalar_offset, bytemuck::cast_slice(&systems));
+        queue.write_buffer(&self.buffers.species_buffer, scalar_offset, bytemuck::cast_slice(&species));
+
+        let range = &mut self.system_ranges[system as usize];
+        //compaction leaves emptied members at [0, 0], which extended would cover every other member's bodies
+        if range[0] == range[1] {
+            range[0] = start;
+        }
+        range[1] = end;
+        queue.write_buffer(&self.buffers.ranges_buffer, system as BufferAddress * VEC2_SIZE, bytemuck::cast_slice(&[*range]));
+
+        self.params.num_bodies = end;
+        self.write_params(queue);
+
+        ids
+    }
+
+    /// Removes the bodies with the given ids and compacts the remaining ones.
+    pub async fn remove_bodies(&mut self, device: &Device, queue: &Queue, ids: &[u32]) {
+        let ids = ids.iter().copied().collect::<HashSet<_>>();
+        let current_ids = self.read_ids(device, queue).await;
+
+        let flags = current_ids.iter().map(|id| {
+            !ids.contains(id) as u32
+        }).collect::<Vec<_>>();
+        queue.write_buffer(&self.buffers.flags_buffer, 0, bytemuck::cast_slice(&flags));
+
+        self.compact(device, queue).await;
+    }
+
+    //for when the initial masses buffer was written behind push_bodies' back
+    pub(crate) fn set_initial_masses(&mut self, initial_masses: Vec<f32>) {
+        self.initial_masses = initial_masses;
+    }
+
+    /// Lets the bodies' masses evolve over time, recomputing them every `interval` ticks.
+    pub fn set_mass_evolution(&mut self, mass_evolution: Option<MassEvolution>, interval: u32) {
+        self.mass_evolution = mass_evolution;
+        self.mass_update_interval = interval;
+    }
+
+    fn update_masses(&self, queue: &Queue) {
+        let Some(mass_evolution) = &self.mass_evolution else {
+            return;
+        };
+
+        let masses = self.initial_masses.iter().map(|m| {
+            mass_evolution.mass(self.time, *m)
+        }).collect::<Vec<_>>();
+        queue.write_buffer(&self.buffers.input_masses, 0, bytemuck::cast_slice(&masses));
+    }
+
+    async fn remove_escapers(&mut self, device: &Device, queue: &Queue) -> std::io::Result<()> {
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor { label: Some("Escape encoder") },
+        );
+        self.escape.encode(&mut encoder, &self.buffers, self.num_systems());
+        queue.submit(Some(encoder.finish()));
+
+        //a log that couldn't be written to still leaves escapers behind to drop
+        let collected = self.escape.collect(device, queue, &self.buffers, self.time).await;
+        if !matches!(collected, Ok(0)) {
+            self.compact(device, queue).await;
+        }
+        collected.map(|_| ())
+    }
+
+    /// Drops every body whose flag is cleared, keeping the survivors contiguous and in their original order.
+    pub async fn compact(&mut self, device: &Device, queue: &Queue) {
+        let num_bodies = self.params.num_bodies;
+        if num_bodies == 0 {
+            return;
+        }
+
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor { label: Some("Compaction encoder") },
+        );
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&Default::default());
+            compute_pass.set_bind_group(0, &self.compact_bind_group, &[]);
+            compute_pass.set_pipeline(&self.scan_pipeline);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+
+        for (buffer, element_size) in self.buffers.attributes() {
+            let (pipeline, layout, src_binding) = if element_size == VEC2_SIZE {
+                (&self.scatter_vec_pipeline, &self.scatter_vec_bind_group_layout, 2)
+            } else {
+                (&self.scatter_scalar_pipeline, &self.scatter_scalar_bind_group_layout, 0)
+            };
+
+            let scatter_bind_group = device.create_bind_group(&BindGroupDescriptor {
+                label: Some("Scatter bind group"),
+                layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: src_binding,
+                        resource: buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: src_binding + 1,
+                        resource: self.buffers.scratch_buffer.as_entire_binding(),
+                    },
+                ],
+            });
+
+            {
+                let mut compute_pass = encoder.begin_compute_pass(&Default::default());
+                compute_pass.set_bind_group(0, &self.compact_bind_group, &[]);
+                compute_pass.set_bind_group(1, &scatter_bind_group, &[]);
+                compute_pass.set_pipeline(pipeline);
+                compute_pass.dispatch_workgroups(self.workgroup_count(), 1, 1);
+            }
+
+            encoder.copy_buffer_to_buffer(&self.buffers.scratch_buffer, 0, buffer, 0, num_bodies as BufferAddress * element_size);
+        }
+
+        let ranges_size = self.num_systems() as BufferAddress * VEC2_SIZE;
+        encoder.clear_buffer(&self.buffers.ranges_buffer, 0, Some(ranges_size));
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&Default::default());
+            compute_pass.set_bind_group(0, &self.compact_bind_group, &[]);
+            compute_pass.set_bind_group(1, &self.ranges_bind_group, &[]);
+            compute_pass.set_pipeline(&self.rebuild_ranges_pipeline);
+            compute_pass.dispatch_workgroups(self.workgroup_count(), 1, 1);
+        }
+
+        queue.submit(Some(encoder.finish()));
+
+        let count = read_buffer::<u32>(device, queue, &self.count_buffer, SCALAR_SIZE).await;
+        self.params.num_bodies = count[0];
+        self.write_params(queue);
+        //only the gpu knows which bodies survived
+        self.initial_masses = read_buffer::<f32>(device, queue, &self.buffers.initial_masses, count[0] as BufferAddress * SCALAR_SIZE).await;
+
+        self.system_ranges = read_buffer::<[u32; 2]>(device, queue, &self.buffers.ranges_buffer, ranges_size).await;
+    }
+
+    /// Reads the live bodies back from the gpu.
+    pub async fn read_bodies(&self, device: &Device, queue: &Queue) -> Vec<Body> {
+        let num_bodies = self.params.num_bodies as BufferAddress;
+        let positions = read_buffer::<[f32; 2]>(device, queue, &self.buffers.positions_buffer, num_bodies * VEC2_SIZE).await;
+        let velocities = read_buffer::<[f32; 2]>(device, queue, &self.buffers.velocities_buffer, num_bodies * VEC2_SIZE).await;
+        let masses = read_buffer::<f32>(device, queue, &self.buffers.input_masses, num_bodies * SCALAR_SIZE).await;
+        let species = read_buffer::<u32>(device, queue, &self.buffers.species_buffer, num_bodies * SCALAR_SIZE).await;
+
+        positions.into_iter().zip(velocities).zip(masses).zip(species).map(|(((position, velocity), mass), species)| {
+            Body::new(mass, position, velocity).with_species(species)
+        }).collect()
+    }
+
+    /// Reads the live bodies back, split up by ensemble member.
+    pub async fn read_members(&self, device: &Device, queue: &Queue) -> Vec<Vec<Body>> {
+        let bodies = self.read_bodies(device, queue).await;
+        self.system_ranges.iter().map(|[start, end]| {
+            bodies[*start as usize..*end as usize].to_vec()
+        }).collect()
+    }
+
+    /// Energy, momentum and center of mass of every ensemble member.
+    pub async fn diagnostics(&self, device: &Device, queue: &Queue) -> Vec<Diagnostics> {
+        let bodies = self.read_bodies(device, queue).await;
+        let potentials = read_buffer::<f32>(device, queue, &self.buffers.potentials_buffer, self.params.num_bodies as BufferAddress * SCALAR_SIZE).await;
+        self.system_ranges.iter().map(|[start, end]| {
+            let range = *start as usize..*end as usize;
+            Diagnostics::from_bodies(&bodies[range.clone()], &potentials[range])
+        }).collect()
+    }
+
+    /// Hash of the full body state, two runs with the same seed in deterministic mode produce the same hash on every tick.
+    pub async fn state_hash(&self, device: &Device, queue: &Queue) -> u64 {
+        let mut hash = fnv1a(FNV_OFFSET_BASIS, &self.tick_count.to_le_bytes());
+        for (buffer, element_size) in self.buffers.attributes() {
+            let data = read_buffer::<u8>(device, queue, buffer, self.params.num_bodies as BufferAddress * element_size).await;
+            hash = fnv1a(hash, &data);
+        }
+        hash
+    }
+
+    /// Rng for anything random that happens during the run, derived from the seed and the current tick so it does not
+    /// depend on what happened before.
+    pub fn rng(&self) -> SmallRng {
+        SmallRng::seed_from_u64(self.seed ^ self.tick_count.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+    }
+
+    /// Reads the potential of every live body, as of the last tick.
+    pub async fn read_potentials(&self, device: &Device, queue: &Queue) -> Vec<f32> {
+        read_buffer::<f32>(device, queue, &self.buffers.potentials_buffer, self.params.num_bodies as BufferAddress * SCALAR_SIZE).await
+    }
+
+    /// Reads the ids of the live bodies, in buffer order.
+    pub async fn read_ids(&self, device: &Device, queue: &Queue) -> Vec<u32> {
+        read_buffer::<u32>(device, queue, &self.buffers.ids_buffer, self.params.num_bodies as BufferAddress * SCALAR_SIZE).await
+    }
+
+    //reallocates every body buffer with at least `min_capacity` room, doubling the capacity each time
+    fn grow(&mut self, device: &Device, queue: &Queue, min_capacity: u32) {
+        let mut capacity = self.buffers.capacity;
+        while capacity < min_capacity {
+            capacity *= 2;
+        }
+
+        let buffers = BodyBuffers::new(device, capacity);
+
+        if self.params.num_bodies > 0 {
+            let mut encoder = device.create_command_encoder(
+                &wgpu::CommandEncoderDescriptor { label: Some("Buffer growth encoder") },
+            );
+            for ((old, element_size), (new, _)) in self.buffers.attributes().into_iter().zip(buffers.attributes()) {
+                encoder.copy_buffer_to_buffer(old, 0, new, 0, self.params.num_bodies as BufferAddress * element_size);
+            }
+            encoder.copy_buffer_to_buffer(&self.buffers.ranges_buffer, 0, &buffers.ranges_buffer, 0, self.num_systems() as BufferAddress * VEC2_SIZE);
+            queue.submit(Some(encoder.finish()));
+        }
+
+        self.input_bind_group = create_input_bind_group(device, &self.input_bind_group_layout, &buffers, &self.params_buffer);
+        self.compact_bind_group = create_compact_bind_group(device, &self.compact_bind_group_layout, &buffers, &self.params_buffer, &self.count_buffer);
+        self.ranges_bind_group = create_ranges_bind_group(device, &self.ranges_bind_group_layout, &buffers);
+        self.escape.rebind(device, &buffers, &self.params_buffer);
+        self.buffers = buffers;
+    }
+
+    pub(crate) fn write_params(&self, queue: &Queue) {
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[self.params]));
+    }
+
+    fn workgroup_count(&self) -> u32 {
+        self.params.num_bodies.div_ceil(WORKGROUP_SIZE)
+    }
+}
+
+pub(crate) fn storage_layout_entry(binding: u32, read_only: bool) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+pub(crate) fn uniform_layout_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+fn create_input_bind_group(device: &Device, layout: &BindGroupLayout, buffers: &BodyBuffers, params_buffer: &Buffer) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Input Bind Group"),
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: buffers.positions_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: buffers.input_masses.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: buffers.velocities_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: params_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 4,
+                resource: buffers.potentials_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 5,
+                resource: buffers.systems_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 6,
+                resource: buffers.ranges_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+fn create_ranges_bind_group(device: &Device, layout: &BindGroupLayout, buffers: &BodyBuffers) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Ranges Bind Group"),
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 4,
+                resource: buffers.systems_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 5,
+                resource: buffers.ranges_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+fn create_compact_bind_group(device: &Device, layout: &BindGroupLayout, buffers: &BodyBuffers, params_buffer: &Buffer, count_buffer: &Buffer) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Compaction Bind Group"),
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: params_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: buffers.flags_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: buffers.destinations_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: count_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+//std's hashers aren't guaranteed to be stable between releases, which would defeat comparing hashes across runs
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// Copies the first `size` bytes of a gpu buffer into a staging buffer and reads them back.
+pub async fn read_buffer<T: bytemuck::Pod>(device: &Device, queue: &Queue, buffer: &Buffer, size: BufferAddress) -> Vec<T> {
+    if size == 0 {
+        return Vec::new();
+    }
+
+    let staging_buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("Readback staging buffer"),
+        size,
+        usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(
+        &wgpu::CommandEncoderDescriptor { label: Some("Readback encoder") },
+    );
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging_buffer, 0, size);
+    queue.submit(Some(encoder.finish()));
+
+    let buffer_slice = staging_buffer.slice(..);
+    let (tx, rx) = futures_intrusive::channel::shared::oneshot_channel();
+    buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+        tx.send(result).unwrap();
+    });
+    device.poll(Maintain::Wait);
+
+    match rx.receive().await {
+        Some(Ok(())) => {
+            let data = bytemuck::cast_slice(&buffer_slice.get_mapped_range()).to_vec();
+            staging_buffer.unmap();
+            data
+        }
+        _ => panic!("Failed to read back {:?}", buffer),
     }
 }
 
+
 //struct to be passed to gpu
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, serde::Serialize, serde::Deserialize)]
 pub struct Body {
     pub position: [f32; 2],
     pub mass: f32,
     pub velocity: [f32; 2],
+    //what kind of body this is, 0 for untagged. only used by importers and exporters, see `gadget`
+    #[serde(default)]
+    pub species: u32,
 }
 
 impl Body {
@@ -196,12 +1027,21 @@ impl Body {
             mass,
             position,
             velocity,
+            species: 0,
+        }
+    }
+
+    pub fn with_species(self, species: u32) -> Self {
+        Self {
+            species,
+            ..self
         }
     }
 }
 
 //galaxies should be spinning counter-clockwise probably
-pub fn gen_actual_spir_g(center_pos: [f32; 2], center_velocity: [f32;2], center_mass: f32, num_bodies: u32, num_arms: u32, clockwise: bool, radius: f32) -> Vec<Body> {
+#[allow(clippy::too_many_arguments)]
+pub fn gen_actual_spir_g(center_pos: [f32; 2], center_velocity: [f32;2], center_mass: f32, num_bodies: u32, num_arms: u32, clockwise: bool, radius: f32, rng: &mut impl Rng) -> Vec<Body> {
     let center_body = Body::new(center_mass, center_pos, center_velocity);
 
     let p_per_arm = (num_bodies / num_arms);
@@ -235,7 +1075,6 @@ pub fn gen_actual_spir_g(center_pos: [f32; 2], center_velocity: [f32;2], center_
 
     //generate particle cloud with all the remaining particles of the num_bodies (maybe replace the num_bodies here with something else)
     // Generate particles that don't belong to the arms
-    let mut rng = rand::thread_rng();
     let mut other_particles = Vec::new();
     for _ in 0..(num_bodies) {
         let angle = rng.gen_range(0.0..2.0 * PI);
@@ -266,11 +1105,11 @@ pub fn generate_spiral_galaxy(
     amount_of_particles: u32,
     center_mass: f32,
     radius: f32,
+    rng: &mut impl Rng,
 ) -> Vec<Body> {
     const RADIUS_EXPONENT: f32 = 0.2;
     const ANGLE_EXPONENT: f32 = 0.5;
 
-    let mut rng = rand::rngs::SmallRng::from_entropy();
     let normal_distribution = Normal::new(0.0f32, 1.0f32).unwrap();
 
     let mut bodies = Vec::with_capacity(amount_of_particles as usize);
@@ -279,7 +1118,7 @@ pub fn generate_spiral_galaxy(
         let radius = rng.gen::<f32>().powf(RADIUS_EXPONENT) * radius;
         let angle = rng.gen::<f32>() * 2.0 * PI;
 
-        let angle_noise = normal_distribution.sample(&mut rng);
+        let angle_noise = normal_distribution.sample(rng);
         let angle_with_noise = angle + angle_noise * ANGLE_EXPONENT;
 
         let x = center_pos[0] + radius * angle_with_noise.cos();
@@ -294,12 +1133,12 @@ pub fn generate_spiral_galaxy(
 
         let velocity = [velocity_x, velocity_y];
 
-        bodies.push(Body::new(1.0, velocity, [x, y]));
+        bodies.push(Body::new(1.0, [x, y], velocity));
     }
 
     let mut central_bodies = Vec::with_capacity(4);
     for _ in 0..4 {
-        central_bodies.push(Body::new(center_mass, [0.0, 0.0], center_pos));
+        central_bodies.push(Body::new(center_mass, center_pos, [0.0, 0.0]));
     }
 
     bodies.extend(central_bodies);
@@ -308,3 +1147,89 @@ pub fn generate_spiral_galaxy(
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenario::Scenario;
+
+    fn spiral(seed: u64) -> Vec<Body> {
+        gen_actual_spir_g([0.0, 32.5], [2.0, 6.0], 50_000.0, 1_000, 2, true, 35.0, &mut SmallRng::seed_from_u64(seed))
+    }
+
+    fn bytes(bodies: &[Body]) -> &[u8] {
+        bytemuck::cast_slice(bodies)
+    }
+
+    #[test]
+    fn generators_are_reproducible() {
+        assert_eq!(bytes(&spiral(3)), bytes(&spiral(3)));
+        assert_ne!(bytes(&spiral(3)), bytes(&spiral(4)));
+
+        let scenario = Scenario::preset("minor_merger").unwrap();
+        let first = scenario.build_components().unwrap().concat();
+        let second = scenario.build_components().unwrap().concat();
+        assert_eq!(bytes(&first), bytes(&second));
+    }
+
+    //needs a gpu, skipped without one
+    #[tokio::test]
+    async fn ensemble_members_dont_interact() {
+        let Some((device, queue)) = crate::try_request_device().await else {
+            eprintln!("no gpu adapter, skipping");
+            return;
+        };
+
+        let pair = vec![
+            Body::new(10.0, [-1.0, 0.0], [0.0, -1.5]),
+            Body::new(10.0, [1.0, 0.0], [0.0, 1.5]),
+        ];
+        //heavy enough to tear the pair apart if it could feel it
+        let intruder = vec![Body::new(1000.0, [0.0, 2.0], [0.0, 0.0])];
+
+        let run = |members: Vec<Vec<Body>>| {
+            let (device, queue) = (&device, &queue);
+            async move {
+                let mut sim_state = SimState::new_ensemble(device, queue, &members);
+                sim_state.deterministic = true;
+                for _ in 0..50 {
+                    sim_state.tick(device, queue).await.unwrap();
+                }
+                (sim_state.read_members(device, queue).await, sim_state.diagnostics(device, queue).await)
+            }
+        };
+        let (alone, _) = run(vec![pair.clone()]).await;
+        let (together, diagnostics) = run(vec![pair, intruder]).await;
+
+        assert_eq!(together.len(), 2);
+        assert_eq!(bytes(&together[0]), bytes(&alone[0]));
+        //the intruder has nothing to fall towards
+        assert_eq!(together[1][0].position, [0.0, 2.0]);
+        assert_eq!(diagnostics.iter().map(|d| d.num_bodies).collect::<Vec<_>>(), [2, 1]);
+        assert!(diagnostics[0].momentum.iter().all(|p| p.abs() < 1e-3));
+    }
+
+    //needs a gpu, skipped without one
+    #[tokio::test]
+    async fn deterministic_runs_hash_the_same() {
+        let Some((device, queue)) = crate::try_request_device().await else {
+            eprintln!("no gpu adapter, skipping");
+            return;
+        };
+
+        let mut hashes = Vec::new();
+        for _ in 0..2 {
+            let bodies = spiral(3);
+            let mut sim_state = SimState::empty(&device, bodies.len() as u32);
+            sim_state.deterministic = true;
+            sim_state.add_system(&device, &queue, &bodies);
+            let mut run = Vec::new();
+            for _ in 0..20 {
+                sim_state.tick(&device, &queue).await.unwrap();
+                run.push(sim_state.state_hash(&device, &queue).await);
+            }
+            hashes.push(run);
+        }
+        assert_eq!(hashes[0], hashes[1]);
+    }
+}
@@ -0,0 +1,357 @@
+//where the rendered frames go: a file per frame, an animated GIF or APNG, or a raw YUV4MPEG2 stream that any encoder
+//can read from a file or a pipe.
+//the expensive part of every frame (quantizing, compressing, converting colors) runs on the readback worker that got
+//the frame, in parallel with the other workers. workers can finish out of order, so encoded frames wait until every
+//earlier frame has been written. the animations are left valid after every frame, a run that gets killed still leaves
+//a playable file behind.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+use crate::frames::{FrameInfo, ImageOutput};
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+const GIF_TRAILER: u8 = 0x3b;
+//1 is the slowest and best looking, 30 the fastest
+const GIF_QUANTIZER_SPEED: i32 = 10;
+
+/// Where the rendered frames go.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "sink", rename_all = "lowercase")]
+pub enum FrameOutput {
+    //a file per frame, output/<frame>.jpeg unless configured otherwise
+    #[serde(alias = "jpeg")]
+    Images(ImageOutput),
+    Gif {
+        path: PathBuf,
+        #[serde(default = "default_fps")]
+        fps: u32,
+    },
+    Apng {
+        path: PathBuf,
+        #[serde(default = "default_fps")]
+        fps: u32,
+    },
+    //"-" as the path streams to stdout
+    Y4m {
+        path: PathBuf,
+        #[serde(default = "default_fps")]
+        fps: u32,
+    },
+}
+
+fn default_fps() -> u32 {
+    30
+}
+
+impl Default for FrameOutput {
+    fn default() -> Self {
+        FrameOutput::Images(ImageOutput::default())
+    }
+}
+
+impl FrameOutput {
+    pub fn to_stdout(&self) -> bool {
+        matches!(self, FrameOutput::Y4m { path, .. } if path == Path::new("-"))
+    }
+}
+
+/// Takes the frames coming back from the gpu, as tightly packed RGBA rows from the top.
+pub struct FrameSink {
+    output: FrameOutput,
+    width: u32,
+    height: u32,
+    stream: Option<Mutex<Stream>>,
+}
+
+struct Stream {
+    encoder: StreamEncoder,
+    //the frame that has to be written next
+    next_sequence: u64,
+    //frames encoded before an earlier one was
+    pending: BTreeMap<u64, EncodedFrame>,
+}
+
+enum StreamEncoder {
+    Gif(gif::Encoder<BufWriter<File>>),
+    Apng(ApngWriter),
+    Y4m(BufWriter<Box<dyn Write + Send>>),
+}
+
+enum EncodedFrame {
+    Gif(gif::Frame<'static>),
+    //zlib compressed, filtered rows
+    Apng(Vec<u8>),
+    //the planes, with the frame header
+    Y4m(Vec<u8>),
+}
+
+impl FrameSink {
+    /// Creates the output file of a streamed output, and its directory if needed. Image settings are only checked,
+    /// their directory gets created with the first frame.
+    pub fn open(output: &FrameOutput, width: u32, height: u32) -> std::io::Result<Self> {
+        let create = |path: &Path| -> std::io::Result<BufWriter<File>> {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            Ok(BufWriter::new(File::create(path)?))
+        };
+
+        let encoder = match output {
+            FrameOutput::Images(images) => {
+                images.validate()?;
+                None
+            }
+            FrameOutput::Gif { path, .. } => {
+                let (width, height) = gif_size(width, height)?;
+                let mut encoder = gif::Encoder::new(create(path)?, width, height, &[]).map_err(gif_error)?;
+                encoder.set_repeat(gif::Repeat::Infinite).map_err(gif_error)?;
+                Some(StreamEncoder::Gif(encoder))
+            }
+            FrameOutput::Apng { path, fps } => {
+                Some(StreamEncoder::Apng(ApngWriter::new(create(path)?, width, height, *fps)?))
+            }
+            FrameOutput::Y4m { path, fps } => {
+                let writer: Box<dyn Write + Send> = if output.to_stdout() {
+                    Box::new(std::io::stdout())
+                } else {
+                    Box::new(create(path)?)
+                };
+                let mut writer = BufWriter::new(writer);
+                //4:2:0 with the chroma centered between the lumas, like jpeg and most encoders expect
+                writeln!(writer, "YUV4MPEG2 W{width} H{height} F{fps}:1 Ip A1:1 C420jpeg")?;
+                Some(StreamEncoder::Y4m(writer))
+            }
+        };
+
+        Ok(Self {
+            output: output.clone(),
+            width,
+            height,
+            stream: encoder.map(|encoder| Mutex::new(Stream {
+                encoder,
+                next_sequence: 0,
+                pending: BTreeMap::new(),
+            })),
+        })
+    }
+
+    pub fn output(&self) -> &FrameOutput {
+        &self.output
+    }
+
+    /// Writes a frame. `sequence` counts the frames from 0 in the order they were rendered.
+    pub fn write(&self, sequence: u64, info: &FrameInfo, rgba: Vec<u8>) -> std::io::Result<()> {
+        let Some(stream) = &self.stream else {
+            let FrameOutput::Images(images) = &self.output else { unreachable!() };
+            return images.save(sequence, info, self.width, self.height, rgba);
+        };
+
+        let frame = self.encode(rgba);
+        let Stream { encoder, next_sequence, pending } = &mut *stream.lock().unwrap();
+        pending.insert(sequence, frame);
+        while let Some(frame) = pending.remove(next_sequence) {
+            encoder.write(frame)?;
+            *next_sequence += 1;
+        }
+        Ok(())
+    }
+
+    fn encode(&self, mut rgba: Vec<u8>) -> EncodedFrame {
+        match self.output {
+            FrameOutput::Images(_) => unreachable!(),
+            FrameOutput::Gif { fps, .. } => {
+                let mut frame = gif::Frame::from_rgba_speed(self.width as u16, self.height as u16, &mut rgba, GIF_QUANTIZER_SPEED);
+                //in hundredths of a second
+                frame.delay = (100.0 / fps as f32).round().max(1.0) as u16;
+                frame.make_lzw_pre_encoded();
+                EncodedFrame::Gif(frame)
+            }
+            FrameOutput::Apng { .. } => EncodedFrame::Apng(png_image_data(&rgba, self.width)),
+            FrameOutput::Y4m { .. } => EncodedFrame::Y4m(yuv420(&rgba, self.width, self.height)),
+        }
+    }
+}
+
+impl StreamEncoder {
+    fn write(&mut self, frame: EncodedFrame) -> std::io::Result<()> {
+        match (self, frame) {
+            (StreamEncoder::Gif(encoder), EncodedFrame::Gif(frame)) => {
+                encoder.write_lzw_pre_encoded_frame(&frame).map_err(gif_error)?;
+                //ends the file after every frame, the next frame overwrites the trailer
+                let writer = encoder.get_mut();
+                writer.write_all(&[GIF_TRAILER])?;
+                writer.flush()?;
+                writer.seek(SeekFrom::Current(-1))?;
+                Ok(())
+            }
+            (StreamEncoder::Apng(writer), EncodedFrame::Apng(data)) => writer.write_frame(&data),
+            (StreamEncoder::Y4m(writer), EncodedFrame::Y4m(data)) => {
+                writer.write_all(&data)?;
+                writer.flush()
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// An APNG that grows by a frame at a time. The frame count in the header is updated after every frame, the end
+/// chunk gets overwritten by the next one.
+struct ApngWriter {
+    writer: BufWriter<File>,
+    width: u32,
+    height: u32,
+    fps: u32,
+    frames: u32,
+    //numbers the frame control and frame data chunks
+    sequence: u32,
+    //where the animation control chunk is
+    actl_position: u64,
+}
+
+impl ApngWriter {
+    fn new(mut writer: BufWriter<File>, width: u32, height: u32, fps: u32) -> std::io::Result<Self> {
+        writer.write_all(&PNG_SIGNATURE)?;
+        //8 bit RGBA, not interlaced
+        let mut ihdr = Vec::new();
+        ihdr.extend(width.to_be_bytes());
+        ihdr.extend(height.to_be_bytes());
+        ihdr.extend([8, 6, 0, 0, 0]);
+        write_chunk(&mut writer, b"IHDR", &ihdr)?;
+
+        let actl_position = writer.stream_position()?;
+        let mut apng = Self {
+            writer,
+            width,
+            height,
+            fps,
+            frames: 0,
+            sequence: 0,
+            actl_position,
+        };
+        apng.write_actl()?;
+        Ok(apng)
+    }
+
+    fn write_actl(&mut self) -> std::io::Result<()> {
+        //frame count, then 0 plays, which loops forever
+        let mut actl = Vec::new();
+        actl.extend(self.frames.to_be_bytes());
+        actl.extend(0u32.to_be_bytes());
+        write_chunk(&mut self.writer, b"acTL", &actl)
+    }
+
+    fn write_frame(&mut self, data: &[u8]) -> std::io::Result<()> {
+        //full frame, shown for 1/fps seconds, replacing the previous one
+        let mut fctl = Vec::new();
+        fctl.extend(self.sequence.to_be_bytes());
+        fctl.extend(self.width.to_be_bytes());
+        fctl.extend(self.height.to_be_bytes());
+        fctl.extend(0u32.to_be_bytes());
+        fctl.extend(0u32.to_be_bytes());
+        fctl.extend(1u16.to_be_bytes());
+        fctl.extend((self.fps as u16).to_be_bytes());
+        fctl.extend([0, 0]);
+        write_chunk(&mut self.writer, b"fcTL", &fctl)?;
+        self.sequence += 1;
+
+        //the first frame is also the image viewers without APNG support show
+        if self.frames == 0 {
+            write_chunk(&mut self.writer, b"IDAT", data)?;
+        } else {
+            let mut fdat = Vec::with_capacity(data.len() + 4);
+            fdat.extend(self.sequence.to_be_bytes());
+            fdat.extend(data);
+            write_chunk(&mut self.writer, b"fdAT", &fdat)?;
+            self.sequence += 1;
+        }
+        self.frames += 1;
+
+        write_chunk(&mut self.writer, b"IEND", &[])?;
+        self.writer.seek(SeekFrom::Start(self.actl_position))?;
+        self.write_actl()?;
+        //the next frame starts where the end chunk is
+        self.writer.seek(SeekFrom::End(-12))?;
+        self.writer.flush()
+    }
+}
+
+fn write_chunk(writer: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> std::io::Result<()> {
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    writer.write_all(&crc.finalize().to_be_bytes())
+}
+
+//every row with the up filter, which suits the mostly dark frames, then compressed
+fn png_image_data(rgba: &[u8], width: u32) -> Vec<u8> {
+    let row_size = width as usize * 4;
+    let mut filtered = Vec::with_capacity(rgba.len() + rgba.len() / row_size);
+    let mut above: &[u8] = &vec![0; row_size];
+    for row in rgba.chunks(row_size) {
+        filtered.push(2);
+        filtered.extend(row.iter().zip(above).map(|(value, up)| value.wrapping_sub(*up)));
+        above = row;
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&filtered).unwrap();
+    encoder.finish().unwrap()
+}
+
+//BT.601 with studio range, chroma averaged over every 2x2 block
+fn yuv420(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+    let pixel = |x: usize, y: usize| {
+        let i = (y.min(height - 1) * width + x.min(width - 1)) * 4;
+        [rgba[i] as f32, rgba[i + 1] as f32, rgba[i + 2] as f32]
+    };
+
+    let mut data = Vec::with_capacity(6 + width * height + 2 * chroma_width * chroma_height);
+    data.extend(b"FRAME\n");
+    for y in 0..height {
+        for x in 0..width {
+            let [r, g, b] = pixel(x, y);
+            data.push((16.0 + 0.256788 * r + 0.504129 * g + 0.097906 * b).round() as u8);
+        }
+    }
+
+    let mut u = Vec::with_capacity(chroma_width * chroma_height);
+    let mut v = Vec::with_capacity(chroma_width * chroma_height);
+    for y in (0..height).step_by(2) {
+        for x in (0..width).step_by(2) {
+            let mut sum = [0.0; 3];
+            for [r, g, b] in [pixel(x, y), pixel(x + 1, y), pixel(x, y + 1), pixel(x + 1, y + 1)] {
+                sum = [sum[0] + r * 0.25, sum[1] + g * 0.25, sum[2] + b * 0.25];
+            }
+            let [r, g, b] = sum;
+            u.push((128.0 - 0.148223 * r - 0.290993 * g + 0.439216 * b).round() as u8);
+            v.push((128.0 + 0.439216 * r - 0.367788 * g - 0.071427 * b).round() as u8);
+        }
+    }
+    data.extend(u);
+    data.extend(v);
+    data
+}
+
+fn gif_size(width: u32, height: u32) -> std::io::Result<(u16, u16)> {
+    match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(width), Ok(height)) => Ok((width, height)),
+        _ => Err(std::io::Error::other(format!("{width}x{height} is too large for a GIF"))),
+    }
+}
+
+fn gif_error(e: gif::EncodingError) -> std::io::Error {
+    std::io::Error::other(e)
+}
@@ -0,0 +1,345 @@
+//versioned binary snapshots of a whole simulation, for checkpointing and restarting runs.
+//a header with the scalar state, then the system ranges, then the raw contents of every body attribute buffer. the
+//header is little endian, the buffer contents are copied as they are (every platform wgpu runs on is little endian).
+//restarting from one continues bit for bit, the rng is derived from the seed and tick count.
+//the mass evolution model (it can be arbitrary code) and the escaper log file are not part of it.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
+
+use wgpu::{BufferAddress, Device, Queue};
+
+use crate::escape::{EscapeSettings, EscapeStats};
+use crate::file_io::read_bytes;
+use crate::sim::{read_buffer, BodyBuffers, SimParams, SimState};
+use crate::units::UnitSystem;
+
+const MAGIC: [u8; 8] = *b"NBODYSNP";
+//goes up whenever the header or the set of attributes changes. 2 added body species
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+pub struct Snapshot {
+    pub params: SimParams,
+    pub units: UnitSystem,
+    pub time: f32,
+    pub tick_count: u64,
+    pub seed: u64,
+    pub deterministic: bool,
+    pub next_id: u32,
+    pub mass_update_interval: u32,
+    //radius and interval, the log path is left out
+    pub escape: Option<(f32, u32)>,
+    pub escape_stats: EscapeStats,
+    pub system_ranges: Vec<[u32; 2]>,
+    //contents of every body attribute buffer, in `BodyBuffers::attributes` order, `num_bodies` elements each
+    pub attributes: Vec<Vec<u8>>,
+}
+
+impl Snapshot {
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+
+        let params = &self.params;
+        writer.write_all(&params.num_bodies.to_le_bytes())?;
+        for value in [params.dt, params.escape_radius, params.gravitational_constant, params.min_distance_sq] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.write_all(&units_tag(self.units).to_le_bytes())?;
+        writer.write_all(&self.time.to_le_bytes())?;
+        writer.write_all(&self.tick_count.to_le_bytes())?;
+        writer.write_all(&self.seed.to_le_bytes())?;
+        writer.write_all(&(self.deterministic as u32).to_le_bytes())?;
+        writer.write_all(&self.next_id.to_le_bytes())?;
+        writer.write_all(&self.mass_update_interval.to_le_bytes())?;
+
+        let (escape_radius, escape_interval) = self.escape.unwrap_or((0.0, 0));
+        writer.write_all(&(self.escape.is_some() as u32).to_le_bytes())?;
+        writer.write_all(&escape_radius.to_le_bytes())?;
+        writer.write_all(&escape_interval.to_le_bytes())?;
+        writer.write_all(&self.escape_stats.escaped.to_le_bytes())?;
+        writer.write_all(&self.escape_stats.escaped_mass.to_le_bytes())?;
+
+        writer.write_all(&(self.system_ranges.len() as u32).to_le_bytes())?;
+        for value in self.system_ranges.iter().flatten() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+
+        writer.write_all(&(self.attributes.len() as u32).to_le_bytes())?;
+        for attribute in &self.attributes {
+            writer.write_all(&(attribute.len() as u64).to_le_bytes())?;
+            writer.write_all(attribute)?;
+        }
+        Ok(())
+    }
+
+    pub fn read(reader: &mut impl Read) -> std::io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a snapshot file"));
+        }
+        let version = read_u32(reader)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, format!("unsupported snapshot version {version}, expected {SNAPSHOT_VERSION}")));
+        }
+
+        let params = SimParams {
+            num_bodies: read_u32(reader)?,
+            dt: f32::from_bits(read_u32(reader)?),
+            escape_radius: f32::from_bits(read_u32(reader)?),
+            gravitational_constant: f32::from_bits(read_u32(reader)?),
+            min_distance_sq: f32::from_bits(read_u32(reader)?),
+            _padding: [0; 3],
+        };
+        let units = match read_u32(reader)? {
+            0 => UnitSystem::Code,
+            1 => UnitSystem::Galactic,
+            2 => UnitSystem::Planetary,
+            tag => return Err(Error::new(ErrorKind::InvalidData, format!("unknown unit system {tag}"))),
+        };
+        let time = f32::from_bits(read_u32(reader)?);
+        let tick_count = read_u64(reader)?;
+        let seed = read_u64(reader)?;
+        let deterministic = read_u32(reader)? != 0;
+        let next_id = read_u32(reader)?;
+        let mass_update_interval = read_u32(reader)?;
+
+        let has_escape = read_u32(reader)? != 0;
+        let escape_radius = f32::from_bits(read_u32(reader)?);
+        let escape_interval = read_u32(reader)?;
+        let escape_stats = EscapeStats {
+            escaped: read_u32(reader)?,
+            escaped_mass: f32::from_bits(read_u32(reader)?),
+        };
+
+        let num_systems = read_u32(reader)?;
+        let system_ranges = (0..num_systems).map(|_| Ok([read_u32(reader)?, read_u32(reader)?])).collect::<std::io::Result<Vec<_>>>()?;
+
+        //checked before anything gets read, and then only allocated as the data comes in, so a damaged file can't ask
+        //for arbitrary amounts of memory
+        let num_attributes = read_u32(reader)?;
+        if num_attributes as usize != BodyBuffers::ATTRIBUTE_SIZES.len() {
+            return Err(attribute_count_error(num_attributes as usize));
+        }
+        let attributes = BodyBuffers::ATTRIBUTE_SIZES.iter().enumerate().map(|(i, element_size)| {
+            let len = read_u64(reader)?;
+            if len != params.num_bodies as u64 * element_size {
+                return Err(attribute_len_error(i, len, params.num_bodies));
+            }
+            read_bytes(reader, len)
+        }).collect::<std::io::Result<Vec<_>>>()?;
+
+        let snapshot = Self {
+            params,
+            units,
+            time,
+            tick_count,
+            seed,
+            deterministic,
+            next_id,
+            mass_update_interval,
+            escape: has_escape.then_some((escape_radius, escape_interval)),
+            escape_stats,
+            system_ranges,
+            attributes,
+        };
+        snapshot.validate()?;
+        Ok(snapshot)
+    }
+
+    /// Checks that there is every attribute with `num_bodies` elements, and that the system ranges are within them.
+    pub fn validate(&self) -> std::io::Result<()> {
+        let num_bodies = self.params.num_bodies;
+        if self.attributes.len() != BodyBuffers::ATTRIBUTE_SIZES.len() {
+            return Err(attribute_count_error(self.attributes.len()));
+        }
+        for (i, (attribute, element_size)) in self.attributes.iter().zip(BodyBuffers::ATTRIBUTE_SIZES).enumerate() {
+            if attribute.len() as u64 != num_bodies as u64 * element_size {
+                return Err(attribute_len_error(i, attribute.len() as u64, num_bodies));
+            }
+        }
+        if let Some([start, end]) = self.system_ranges.iter().find(|[start, end]| start > end || *end > num_bodies) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("system range {start}..{end} is outside the {num_bodies} bodies")));
+        }
+        Ok(())
+    }
+}
+
+impl SimState {
+    /// Reads the whole simulation state back from the gpu.
+    pub async fn snapshot(&self, device: &Device, queue: &Queue) -> Snapshot {
+        let mut attributes = Vec::new();
+        for (buffer, element_size) in self.buffers.attributes() {
+            attributes.push(read_buffer::<u8>(device, queue, buffer, self.params.num_bodies as BufferAddress * element_size).await);
+        }
+
+        Snapshot {
+            params: self.params,
+            units: self.units,
+            time: self.time,
+            tick_count: self.tick_count,
+            seed: self.seed,
+            deterministic: self.deterministic,
+            next_id: self.next_id,
+            mass_update_interval: self.mass_update_interval,
+            escape: self.escape.settings.as_ref().map(|s| (s.radius, s.interval)),
+            escape_stats: self.escape.stats,
+            system_ranges: self.system_ranges.clone(),
+            attributes,
+        }
+    }
+
+    /// Recreates a simulation from a snapshot. The mass evolution model has to be set again if there was one.
+    pub fn from_snapshot(device: &Device, queue: &Queue, snapshot: &Snapshot) -> std::io::Result<Self> {
+        snapshot.validate()?;
+        let num_bodies = snapshot.params.num_bodies;
+        let mut sim_state = Self::empty(device, num_bodies.max(snapshot.system_ranges.len() as u32));
+
+        for ((buffer, _), data) in sim_state.buffers.attributes().iter().zip(&snapshot.attributes) {
+            queue.write_buffer(buffer, 0, data);
+        }
+        queue.write_buffer(&sim_state.buffers.ranges_buffer, 0, bytemuck::cast_slice(&snapshot.system_ranges));
+        sim_state.system_ranges = snapshot.system_ranges.clone();
+
+        sim_state.params = snapshot.params;
+        sim_state.write_params(queue);
+        sim_state.units = snapshot.units;
+        sim_state.time = snapshot.time;
+        sim_state.tick_count = snapshot.tick_count;
+        sim_state.seed = snapshot.seed;
+        sim_state.deterministic = snapshot.deterministic;
+        sim_state.next_id = snapshot.next_id;
+        sim_state.mass_update_interval = snapshot.mass_update_interval;
+        sim_state.set_initial_masses(bytemuck::pod_collect_to_vec(&snapshot.attributes[BodyBuffers::INITIAL_MASSES_ATTRIBUTE]));
+
+        //the log isn't restored, so there is nothing to open
+        sim_state.escape.settings = snapshot.escape.map(|(radius, interval)| EscapeSettings {
+            radius,
+            interval,
+            log_path: None,
+        });
+        sim_state.escape.stats = snapshot.escape_stats;
+
+        Ok(sim_state)
+    }
+}
+
+fn attribute_count_error(count: usize) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("snapshot has {count} body attributes, expected {}", BodyBuffers::ATTRIBUTE_SIZES.len()))
+}
+
+fn attribute_len_error(attribute: usize, len: u64, num_bodies: u32) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("body attribute {attribute} is {len} bytes, which doesn't fit {num_bodies} bodies"))
+}
+
+fn units_tag(units: UnitSystem) -> u32 {
+    match units {
+        UnitSystem::Code => 0,
+        UnitSystem::Galactic => 1,
+        UnitSystem::Planetary => 2,
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> std::io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(num_bodies: u32) -> Snapshot {
+        Snapshot {
+            params: SimParams { num_bodies, ..bytemuck::Zeroable::zeroed() },
+            units: UnitSystem::Code,
+            time: 1.5,
+            tick_count: 3,
+            seed: 7,
+            deterministic: true,
+            next_id: num_bodies,
+            mass_update_interval: 1,
+            escape: Some((400.0, 10)),
+            escape_stats: EscapeStats::default(),
+            system_ranges: vec![[0, num_bodies]],
+            attributes: BodyBuffers::ATTRIBUTE_SIZES.iter()
+                .map(|size| (0..num_bodies as u64 * size).map(|i| i as u8).collect())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut data = Vec::new();
+        snapshot(5).write(&mut data).unwrap();
+        let read = Snapshot::read(&mut data.as_slice()).unwrap();
+        assert_eq!(read.params.num_bodies, 5);
+        assert_eq!(read.attributes, snapshot(5).attributes);
+        assert_eq!(read.system_ranges, [[0, 5]]);
+    }
+
+    #[test]
+    fn rejects_attributes_that_dont_fit_the_bodies() {
+        let mut short = snapshot(5);
+        short.attributes[2].truncate(4);
+        assert!(short.validate().is_err());
+        let mut data = Vec::new();
+        short.write(&mut data).unwrap();
+        assert_eq!(Snapshot::read(&mut data.as_slice()).err().unwrap().kind(), ErrorKind::InvalidData);
+
+        let mut missing = snapshot(5);
+        missing.attributes.pop();
+        assert!(missing.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_huge_lengths_before_allocating() {
+        let mut data = Vec::new();
+        snapshot(1).write(&mut data).unwrap();
+        //the attributes, every one a length and its contents, make up the end of the file
+        let first_len = data.len() - BodyBuffers::ATTRIBUTE_SIZES.iter().map(|size| 8 + *size as usize).sum::<usize>();
+        data[first_len..first_len + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(Snapshot::read(&mut data.as_slice()).err().unwrap().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_body_counts_past_the_end_of_the_file() {
+        let mut data = Vec::new();
+        snapshot(1).write(&mut data).unwrap();
+        //num_bodies follows the magic and version, the first attribute's length comes right before its contents
+        let num_bodies = u32::MAX;
+        data[12..16].copy_from_slice(&num_bodies.to_le_bytes());
+        let first_len = data.len() - BodyBuffers::ATTRIBUTE_SIZES.iter().map(|size| 8 + *size as usize).sum::<usize>();
+        data[first_len..first_len + 8].copy_from_slice(&(num_bodies as u64 * BodyBuffers::ATTRIBUTE_SIZES[0]).to_le_bytes());
+        assert_eq!(Snapshot::read(&mut data.as_slice()).err().unwrap().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn rejects_ranges_outside_the_bodies() {
+        let mut snapshot = snapshot(5);
+        snapshot.system_ranges.push([3, 9]);
+        assert!(snapshot.validate().is_err());
+    }
+}
@@ -0,0 +1,236 @@
+//grand design spirals: an exponential disk whose stars crowd into logarithmic spiral arms, optionally with a bar.
+//the arms are a density pattern only, every star moves on the circular orbit of the (azimuthally averaged) mass
+//inside it, the same way the disk galaxy generator works it out.
+
+use std::f32::consts::PI;
+
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+use serde::{Deserialize, Serialize};
+
+use crate::galaxy::{length, RadialProfile};
+use crate::sim::Body;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpiralDisk {
+    pub num_bodies: u32,
+    pub mass: f32,
+    pub center_mass: f32,
+    pub scale_length: f32,
+    //nothing is placed further out than this
+    pub radius: f32,
+
+    pub num_arms: u32,
+    //angle between the arms and a circle, in degrees. ~10 for tightly wound arms, ~30 for open ones
+    pub pitch_angle: f32,
+    //standard deviation of the arms' density profile across them
+    pub arm_width: f32,
+    //density on the arms relative to in between them
+    pub density_contrast: f32,
+    //random velocities, as a fraction of the circular velocity
+    pub velocity_dispersion: f32,
+    pub clockwise: bool,
+
+    pub bar: Option<Bar>,
+}
+
+impl Default for SpiralDisk {
+    fn default() -> Self {
+        Self {
+            num_bodies: 20_000,
+            mass: 20_000.0,
+            center_mass: 20_000.0,
+            scale_length: 10.0,
+            radius: 40.0,
+
+            num_arms: 2,
+            pitch_angle: 15.0,
+            arm_width: 2.0,
+            density_contrast: 4.0,
+            velocity_dispersion: 0.05,
+            clockwise: false,
+
+            bar: None,
+        }
+    }
+}
+
+/// A Ferrers bar rotating as a solid body, with its ends on circular orbits.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bar {
+    pub num_bodies: u32,
+    pub mass: f32,
+    //semi-major axis
+    pub length: f32,
+    //minor over major axis
+    pub axis_ratio: f32,
+    //counter-clockwise from the x axis, in degrees
+    pub angle: f32,
+    pub clockwise: bool,
+}
+
+impl Default for Bar {
+    fn default() -> Self {
+        Self {
+            num_bodies: 4_000,
+            mass: 6_000.0,
+            length: 8.0,
+            axis_ratio: 0.3,
+            angle: 0.0,
+            clockwise: false,
+        }
+    }
+}
+
+impl SpiralDisk {
+    /// Generates the galaxy centered on the origin, at rest. The central mass, if any, is the first body.
+    pub fn generate(&self, rng: &mut impl Rng) -> Vec<Body> {
+        let disk_positions: Vec<[f32; 2]> = (0..self.num_bodies).map(|_| self.sample_position(rng)).collect();
+        let bar_positions: Vec<[f32; 2]> = match &self.bar {
+            Some(bar) => (0..bar.num_bodies).map(|_| bar.sample_position(rng)).collect(),
+            None => Vec::new(),
+        };
+
+        let disk_body_mass = self.mass / self.num_bodies.max(1) as f32;
+        let bar_body_mass = self.bar.as_ref().map_or(0.0, |bar| bar.mass / bar.num_bodies.max(1) as f32);
+        let samples = disk_positions.iter().map(|p| (length(*p), disk_body_mass))
+            .chain(bar_positions.iter().map(|p| (length(*p), bar_body_mass)));
+        let max_radius = disk_positions.iter().chain(&bar_positions).map(|p| length(*p)).fold(0.0f32, f32::max).max(1e-3);
+        let profile = RadialProfile::from_samples(samples, max_radius);
+        let acceleration = profile.radial_acceleration(self.center_mass);
+
+        let normal = Normal::new(0.0f32, 1.0).unwrap();
+        let direction = if self.clockwise { -1.0 } else { 1.0 };
+        let mut bodies = Vec::with_capacity(disk_positions.len() + bar_positions.len() + 1);
+
+        if self.center_mass > 0.0 {
+            bodies.push(Body::new(self.center_mass, [0.0, 0.0], [0.0, 0.0]));
+        }
+
+        for position in disk_positions {
+            let r = length(position);
+            let speed = (acceleration[profile.ring(r)] * r).sqrt();
+            let sigma = speed * self.velocity_dispersion;
+            let [cos, sin] = if r > 0.0 { [position[0] / r, position[1] / r] } else { [1.0, 0.0] };
+            let velocity = [
+                -direction * speed * sin + normal.sample(rng) * sigma,
+                direction * speed * cos + normal.sample(rng) * sigma,
+            ];
+            bodies.push(Body::new(disk_body_mass, position, velocity));
+        }
+
+        if let Some(bar) = &self.bar {
+            let end_speed = (acceleration[profile.ring(bar.length)] * bar.length).sqrt();
+            bodies.extend(bar.bodies(bar_positions, end_speed));
+        }
+
+        bodies
+    }
+
+    //exponential in radius, and in azimuth weighted towards the arms
+    fn sample_position(&self, rng: &mut impl Rng) -> [f32; 2] {
+        let r = loop {
+            let u1: f32 = rng.gen_range(f32::EPSILON..1.0);
+            let u2: f32 = rng.gen_range(f32::EPSILON..1.0);
+            let r = -self.scale_length * (u1 * u2).ln();
+            if r < self.radius {
+                break r;
+            }
+        };
+
+        let contrast = self.density_contrast.max(1.0);
+        loop {
+            let angle = rng.gen_range(0.0..2.0 * PI);
+            if rng.gen_range(0.0..contrast) < self.arm_density(r, angle) {
+                return [r * angle.cos(), r * angle.sin()];
+            }
+        }
+    }
+
+    //1 between the arms, density_contrast on them
+    fn arm_density(&self, r: f32, angle: f32) -> f32 {
+        let num_arms = self.num_arms.max(1) as f32;
+        let pitch = self.pitch_angle.to_radians();
+        //r = e^(tan(pitch) * angle) for the first arm, the others are rotated copies of it.
+        //trailing arms wind the opposite way the disk spins.
+        let direction = if self.clockwise { 1.0 } else { -1.0 };
+        let arm_angle = direction * r.max(1e-3).ln() / pitch.tan();
+
+        //azimuthal offset from the nearest arm, then distance across the arm
+        let spacing = 2.0 * PI / num_arms;
+        let offset = (angle - arm_angle).rem_euclid(spacing);
+        let offset = offset.min(spacing - offset);
+        let distance = r * offset * pitch.sin();
+
+        let arm = (-distance * distance / (2.0 * self.arm_width * self.arm_width)).exp();
+        1.0 + (self.density_contrast.max(1.0) - 1.0) * arm
+    }
+}
+
+impl Bar {
+    /// Generates a bar on its own, centered on the origin. It holds itself together, so it spins at whatever
+    /// speed puts its ends on circular orbits around its own mass.
+    pub fn generate(&self, rng: &mut impl Rng) -> Vec<Body> {
+        let positions: Vec<[f32; 2]> = (0..self.num_bodies).map(|_| self.sample_position(rng)).collect();
+        let body_mass = self.mass / self.num_bodies.max(1) as f32;
+        let max_radius = positions.iter().map(|p| length(*p)).fold(0.0f32, f32::max).max(1e-3);
+        let profile = RadialProfile::from_samples(positions.iter().map(|p| (length(*p), body_mass)), max_radius);
+        let acceleration = profile.radial_acceleration(0.0);
+        let end_speed = (acceleration[profile.ring(self.length)] * self.length).sqrt();
+        self.bodies(positions, end_speed)
+    }
+
+    //Ferrers (n = 1) density, 1 - m^2 inside the ellipse m = 1
+    fn sample_position(&self, rng: &mut impl Rng) -> [f32; 2] {
+        let [x, y] = loop {
+            let x: f32 = rng.gen_range(-1.0..1.0);
+            let y: f32 = rng.gen_range(-1.0..1.0);
+            let m_sq = x * x + y * y;
+            if rng.gen_range(0.0..1.0) < 1.0 - m_sq {
+                break [x * self.length, y * self.length * self.axis_ratio];
+            }
+        };
+        let (sin, cos) = self.angle.to_radians().sin_cos();
+        [x * cos - y * sin, x * sin + y * cos]
+    }
+
+    //solid body rotation, moving the ends of the bar at end_speed
+    fn bodies(&self, positions: Vec<[f32; 2]>, end_speed: f32) -> Vec<Body> {
+        let body_mass = self.mass / self.num_bodies.max(1) as f32;
+        let pattern_speed = end_speed / self.length * if self.clockwise { -1.0 } else { 1.0 };
+        positions.into_iter().map(|[x, y]| {
+            Body::new(body_mass, [x, y], [-pattern_speed * y, pattern_speed * x])
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::diagnostics::testing::sampling_noise;
+    use crate::diagnostics::Diagnostics;
+
+    #[test]
+    fn spirals_have_their_mass_and_sit_still_at_the_origin() {
+        let spiral = SpiralDisk { num_bodies: 8_000, bar: Some(Bar { angle: 30.0, ..Default::default() }), ..Default::default() };
+        let bodies = spiral.generate(&mut SmallRng::seed_from_u64(0));
+        assert_eq!(bodies.len(), 12_001);
+
+        let diagnostics = Diagnostics::from_bodies(&bodies, &vec![0.0; bodies.len()]);
+        let expected_mass = spiral.mass + spiral.center_mass + Bar::default().mass;
+        assert!((diagnostics.mass - expected_mass).abs() < 1e-4 * expected_mass, "{} != {expected_mass}", diagnostics.mass);
+
+        let offset = length(diagnostics.center_of_mass);
+        let position_noise = sampling_noise(&bodies, |body| body.position);
+        assert!(offset < 4.0 * position_noise, "center of mass off by {offset}, noise {position_noise}");
+
+        let drift = length(diagnostics.momentum) / diagnostics.mass;
+        let velocity_noise = sampling_noise(&bodies, |body| body.velocity);
+        assert!(drift < 4.0 * velocity_noise, "bulk velocity {drift}, noise {velocity_noise}");
+    }
+}
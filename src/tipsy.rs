@@ -0,0 +1,232 @@
+//TIPSY binary snapshots, as read and written by pynbody and ChaNGa. a header with the time and the number of gas, dark
+//and star particles, then one array of fixed size records for each of them, in that order.
+//the standard files are big endian with the header padded to 32 bytes, the reader also takes little endian and
+//unpadded files. bodies are written with z = 0 and read seen from above, values are in the simulation's units.
+//gas, dark and star particles map to the gas, halo and star species of the `gadget` module, on write the other stellar
+//species (disk, bulge, boundary) go with the stars and untagged bodies with the dark matter.
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use wgpu::{Device, Queue};
+
+use crate::gadget::{BOUNDARY_SPECIES, BULGE_SPECIES, DISK_SPECIES, GAS_SPECIES, HALO_SPECIES, STAR_SPECIES};
+use crate::file_io::{invalid, Fields};
+use crate::sim::{Body, SimState};
+
+const DIMENSIONS: i32 = 3;
+const HEADER_SIZE: usize = 28;
+const PADDED_HEADER_SIZE: usize = 32;
+//floats per record: mass, position, velocity, then rho, temperature, smoothing length, metals and potential for gas,
+//softening and potential for dark matter, metals, formation time, softening and potential for stars
+const GAS_FLOATS: usize = 12;
+const DARK_FLOATS: usize = 9;
+const STAR_FLOATS: usize = 11;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Endianness {
+    //what the tools expect
+    #[default]
+    Big,
+    Little,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TipsySnapshot {
+    //gas, then dark matter, then stars
+    pub bodies: Vec<Body>,
+    //scale factor in cosmological runs
+    pub time: f64,
+    //gravitational softening written for every particle, the one of the first particle on read
+    pub softening: f32,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Family {
+    Gas,
+    Dark,
+    Star,
+}
+
+fn family(species: u32) -> Family {
+    match species {
+        GAS_SPECIES => Family::Gas,
+        STAR_SPECIES | DISK_SPECIES | BULGE_SPECIES | BOUNDARY_SPECIES => Family::Star,
+        _ => Family::Dark,
+    }
+}
+
+impl TipsySnapshot {
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Self::read(&mut File::open(path)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>, endianness: Endianness) -> std::io::Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer, endianness)?;
+        writer.flush()
+    }
+
+    /// Reads a snapshot in either byte order, with or without the header padding.
+    pub fn read(reader: &mut impl Read) -> std::io::Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        if data.len() < HEADER_SIZE {
+            return Err(invalid("file too short for a header"));
+        }
+
+        //the number of dimensions is always 3, so it gives away the byte order
+        let dimensions: [u8; 4] = data[12..16].try_into().unwrap();
+        let endianness = if i32::from_be_bytes(dimensions) == DIMENSIONS {
+            Endianness::Big
+        } else if i32::from_le_bytes(dimensions) == DIMENSIONS {
+            Endianness::Little
+        } else {
+            return Err(invalid("not a TIPSY snapshot"));
+        };
+
+        let mut fields = Fields::new(&data, endianness == Endianness::Big);
+        let time = fields.f64();
+        //total count and dimensions
+        fields.skip(8);
+        let num_gas = fields.u32() as usize;
+        let num_dark = fields.u32() as usize;
+        let num_star = fields.u32() as usize;
+
+        let records_size = (num_gas * GAS_FLOATS + num_dark * DARK_FLOATS + num_star * STAR_FLOATS) * 4;
+        fields.seek(if data.len() >= PADDED_HEADER_SIZE + records_size {
+            PADDED_HEADER_SIZE
+        } else if data.len() >= HEADER_SIZE + records_size {
+            HEADER_SIZE
+        } else {
+            return Err(invalid("file too short for the particle counts in its header"));
+        });
+
+        let mut bodies = Vec::with_capacity(num_gas + num_dark + num_star);
+        let mut softening = None;
+        for (count, floats, species) in [(num_gas, GAS_FLOATS, GAS_SPECIES), (num_dark, DARK_FLOATS, HALO_SPECIES), (num_star, STAR_FLOATS, STAR_SPECIES)] {
+            for _ in 0..count {
+                let record = (0..floats).map(|_| fields.f32()).collect::<Vec<_>>();
+                bodies.push(Body::new(record[0], [record[1], record[2]], [record[4], record[5]]).with_species(species));
+                if species != GAS_SPECIES {
+                    softening.get_or_insert(record[floats - 2]);
+                }
+            }
+        }
+
+        Ok(Self {
+            bodies,
+            time,
+            softening: softening.unwrap_or(0.0),
+        })
+    }
+
+    /// Writes the bodies with a padded header, reordered into gas, dark matter and stars.
+    pub fn write(&self, writer: &mut impl Write, endianness: Endianness) -> std::io::Result<()> {
+        let families = [Family::Gas, Family::Dark, Family::Star];
+        let counts = families.map(|f| self.bodies.iter().filter(|b| family(b.species) == f).count() as u32);
+
+        let mut out = Output { writer, endianness };
+        out.f64(self.time)?;
+        out.u32(self.bodies.len() as u32)?;
+        out.u32(DIMENSIONS as u32)?;
+        for count in counts {
+            out.u32(count)?;
+        }
+        out.u32(0)?;
+
+        for f in families {
+            for body in self.bodies.iter().filter(|b| family(b.species) == f) {
+                let [x, y] = body.position;
+                let [vx, vy] = body.velocity;
+                for value in [body.mass, x, y, 0.0, vx, vy, 0.0] {
+                    out.f32(value)?;
+                }
+                let rest = match f {
+                    Family::Gas => vec![0.0, 0.0, self.softening, 0.0, 0.0],
+                    Family::Dark => vec![self.softening, 0.0],
+                    Family::Star => vec![0.0, 0.0, self.softening, 0.0],
+                };
+                for value in rest {
+                    out.f32(value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SimState {
+    /// Starts a simulation from a TIPSY snapshot, all bodies in one system.
+    pub fn from_tipsy(device: &Device, queue: &Queue, snapshot: &TipsySnapshot) -> Self {
+        let mut sim_state = Self::empty(device, snapshot.bodies.len() as u32);
+        sim_state.add_system(device, queue, &snapshot.bodies);
+        sim_state.time = snapshot.time as f32;
+        sim_state
+    }
+
+    /// The live bodies of every system as a TIPSY snapshot, with the minimum interaction distance as the softening.
+    pub async fn to_tipsy(&self, device: &Device, queue: &Queue) -> TipsySnapshot {
+        TipsySnapshot {
+            bodies: self.read_bodies(device, queue).await,
+            time: self.time as f64,
+            softening: self.params.min_distance_sq.sqrt(),
+        }
+    }
+}
+
+struct Output<'a, W: Write> {
+    writer: &'a mut W,
+    endianness: Endianness,
+}
+
+impl<W: Write> Output<'_, W> {
+    fn bytes<const N: usize>(&mut self, mut bytes: [u8; N]) -> std::io::Result<()> {
+        if self.endianness == Endianness::Big {
+            bytes.reverse();
+        }
+        self.writer.write_all(&bytes)
+    }
+
+    fn u32(&mut self, value: u32) -> std::io::Result<()> {
+        self.bytes(value.to_le_bytes())
+    }
+
+    fn f32(&mut self, value: f32) -> std::io::Result<()> {
+        self.bytes(value.to_le_bytes())
+    }
+
+    fn f64(&mut self, value: f64) -> std::io::Result<()> {
+        self.bytes(value.to_le_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_in_both_byte_orders() {
+        let snapshot = TipsySnapshot {
+            bodies: vec![
+                Body::new(1.0, [1.0, 2.0], [3.0, 4.0]).with_species(GAS_SPECIES),
+                Body::new(2.0, [5.0, 6.0], [7.0, 8.0]).with_species(HALO_SPECIES),
+                Body::new(3.0, [9.0, 10.0], [11.0, 12.0]).with_species(STAR_SPECIES),
+            ],
+            time: 0.5,
+            softening: 0.1,
+        };
+        for endianness in [Endianness::Big, Endianness::Little] {
+            let mut data = Vec::new();
+            snapshot.write(&mut data, endianness).unwrap();
+            let read = TipsySnapshot::read(&mut data.as_slice()).unwrap();
+            assert_eq!(read.time, snapshot.time);
+            assert_eq!(read.softening, snapshot.softening);
+            assert_eq!(bytemuck::cast_slice::<Body, u8>(&read.bodies), bytemuck::cast_slice::<Body, u8>(&snapshot.bodies));
+        }
+    }
+}
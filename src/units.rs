@@ -0,0 +1,144 @@
+//physical units. the simulation runs directly in the chosen units, only the gravitational constant changes, so
+//scenario values (masses, radii, dt) are given in them and everything read back is in them too.
+//the generators all work with G = 1, the velocities they derive from gravity get rescaled by sqrt(G).
+
+use serde::{Deserialize, Serialize};
+
+use crate::diagnostics::Diagnostics;
+
+const METERS_PER_KPC: f64 = 3.085_677_581e19;
+const METERS_PER_AU: f64 = 1.495_978_707e11;
+const KG_PER_SOLAR_MASS: f64 = 1.988_47e30;
+const SECONDS_PER_YEAR: f64 = 3.155_76e7;
+const SECONDS_PER_GYR: f64 = 3.155_76e16;
+const G_SI: f64 = 6.674_30e-11;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnitSystem {
+    //G = 1, no physical meaning
+    #[default]
+    Code,
+    //kpc, solar masses and Gyr, for galaxies
+    Galactic,
+    //AU, solar masses and years, for planetary systems
+    Planetary,
+}
+
+impl UnitSystem {
+    pub fn gravitational_constant(&self) -> f32 {
+        match self {
+            UnitSystem::Code => 1.0,
+            _ => (G_SI * self.mass_in_kg() * self.time_in_seconds().powi(2) / self.length_in_meters().powi(3)) as f32,
+        }
+    }
+
+    /// Size of one length unit in meters, 1 for code units.
+    pub fn length_in_meters(&self) -> f64 {
+        match self {
+            UnitSystem::Code => 1.0,
+            UnitSystem::Galactic => METERS_PER_KPC,
+            UnitSystem::Planetary => METERS_PER_AU,
+        }
+    }
+
+    pub fn mass_in_kg(&self) -> f64 {
+        match self {
+            UnitSystem::Code => 1.0,
+            UnitSystem::Galactic | UnitSystem::Planetary => KG_PER_SOLAR_MASS,
+        }
+    }
+
+    pub fn time_in_seconds(&self) -> f64 {
+        match self {
+            UnitSystem::Code => 1.0,
+            UnitSystem::Galactic => SECONDS_PER_GYR,
+            UnitSystem::Planetary => SECONDS_PER_YEAR,
+        }
+    }
+
+    /// One solar mass in mass units.
+    pub fn solar_mass(&self) -> f32 {
+        (KG_PER_SOLAR_MASS / self.mass_in_kg()) as f32
+    }
+
+    /// One Gyr in time units.
+    pub fn gyr(&self) -> f32 {
+        (SECONDS_PER_GYR / self.time_in_seconds()) as f32
+    }
+
+    /// One unit of velocity in km/s, 1 for code units.
+    pub fn velocity_in_km_per_s(&self) -> f64 {
+        match self {
+            UnitSystem::Code => 1.0,
+            _ => self.length_in_meters() / self.time_in_seconds() / 1000.0,
+        }
+    }
+
+    pub fn length_name(&self) -> &'static str {
+        match self {
+            UnitSystem::Code => "",
+            UnitSystem::Galactic => " kpc",
+            UnitSystem::Planetary => " AU",
+        }
+    }
+
+    pub fn mass_name(&self) -> &'static str {
+        match self {
+            UnitSystem::Code => "",
+            UnitSystem::Galactic | UnitSystem::Planetary => " Msun",
+        }
+    }
+
+    pub fn time_name(&self) -> &'static str {
+        match self {
+            UnitSystem::Code => "",
+            UnitSystem::Galactic => " Gyr",
+            UnitSystem::Planetary => " yr",
+        }
+    }
+
+    pub fn format_time(&self, time: f32) -> String {
+        format!("{time}{}", self.time_name())
+    }
+
+    pub fn format_length(&self, length: f32) -> String {
+        format!("{length}{}", self.length_name())
+    }
+
+    pub fn format_mass(&self, mass: f32) -> String {
+        format!("{mass}{}", self.mass_name())
+    }
+
+    /// Velocities are reported in km/s outside of code units.
+    pub fn format_velocity(&self, velocity: f32) -> String {
+        match self {
+            UnitSystem::Code => format!("{velocity}"),
+            _ => format!("{} km/s", velocity as f64 * self.velocity_in_km_per_s()),
+        }
+    }
+
+    /// Energies are reported in mass * (km/s)^2 outside of code units.
+    pub fn format_energy(&self, energy: f32) -> String {
+        match self {
+            UnitSystem::Code => format!("{energy}"),
+            _ => format!("{}{} (km/s)^2", energy as f64 * self.velocity_in_km_per_s().powi(2), self.mass_name()),
+        }
+    }
+
+    /// One line summary of the diagnostics of a system.
+    pub fn describe(&self, diagnostics: &Diagnostics) -> String {
+        let [x, y] = diagnostics.center_of_mass;
+        format!(
+            "{} bodies, mass {}, energy {} (kinetic {}, potential {}), virial ratio {}, center of mass ({}, {})",
+            diagnostics.num_bodies,
+            self.format_mass(diagnostics.mass),
+            self.format_energy(diagnostics.total_energy()),
+            self.format_energy(diagnostics.kinetic_energy),
+            self.format_energy(diagnostics.potential_energy),
+            diagnostics.virial_ratio(),
+            self.format_length(x),
+            self.format_length(y),
+        )
+    }
+}
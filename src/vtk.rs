@@ -0,0 +1,180 @@
+//VTK output for ParaView: every frame as an XML PolyData point cloud (.vtp) with the velocity, mass, species,
+//potential and id of every body, and a .pvd collection indexing the frames by simulation time.
+//the data goes after the XML as raw appended binary, every array prefixed with its size in bytes. bodies are at z = 0.
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use wgpu::{Device, Queue};
+
+use crate::sim::{Body, SimState};
+
+//what the collection file ends with, every new frame goes in front of it
+const COLLECTION_END: &str = "  </Collection>\n</VTKFile>\n";
+
+/// Everything written for one frame.
+#[derive(Clone, Debug, Default)]
+pub struct VtkFrame {
+    pub bodies: Vec<Body>,
+    pub ids: Vec<u32>,
+    pub potentials: Vec<f32>,
+}
+
+/// A time series of frames in one directory, the collection file is complete after every frame so it is usable while
+/// the run is still going.
+#[derive(Clone, Debug)]
+pub struct VtkSeries {
+    pub directory: PathBuf,
+    pub name: String,
+    //whether the collection file has been started
+    started: bool,
+}
+
+impl VtkFrame {
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        let n = self.bodies.len();
+        let vectors = |f: fn(&Body) -> [f32; 2]| self.bodies.iter().flat_map(|b| {
+            let [x, y] = f(b);
+            [x, y, 0.0]
+        }).flat_map(f32::to_le_bytes).collect::<Vec<_>>();
+        let connectivity = (0..n as i32).flat_map(i32::to_le_bytes).collect::<Vec<_>>();
+        let offsets = (1..=n as i32).flat_map(i32::to_le_bytes).collect::<Vec<_>>();
+
+        //name, type, components and contents of every array, in the order they appear in the file
+        let point_data = [
+            ("velocity", "Float32", 3, vectors(|b| b.velocity)),
+            ("mass", "Float32", 1, self.bodies.iter().flat_map(|b| b.mass.to_le_bytes()).collect()),
+            ("species", "UInt32", 1, self.bodies.iter().flat_map(|b| b.species.to_le_bytes()).collect()),
+            ("potential", "Float32", 1, padded(&self.potentials, n).flat_map(f32::to_le_bytes).collect()),
+            ("id", "UInt32", 1, padded(&self.ids, n).flat_map(u32::to_le_bytes).collect()),
+        ];
+        let points = ("position", "Float32", 3, vectors(|b| b.position));
+        let verts = [("connectivity", "Int32", 1, connectivity), ("offsets", "Int32", 1, offsets)];
+
+        let mut offset = 0;
+        let mut data_array = |xml: &mut String, (name, kind, components, data): &(&str, &str, u32, Vec<u8>)| {
+            writeln!(
+                xml,
+                r#"        <DataArray type="{kind}" Name="{name}" NumberOfComponents="{components}" format="appended" offset="{offset}"/>"#,
+            ).unwrap();
+            offset += std::mem::size_of::<u32>() + data.len();
+        };
+
+        let mut xml = String::new();
+        writeln!(xml, r#"<?xml version="1.0"?>"#).unwrap();
+        writeln!(xml, r#"<VTKFile type="PolyData" version="1.0" byte_order="LittleEndian" header_type="UInt32">"#).unwrap();
+        writeln!(xml, "  <PolyData>").unwrap();
+        writeln!(xml, r#"    <Piece NumberOfPoints="{n}" NumberOfVerts="{n}" NumberOfLines="0" NumberOfStrips="0" NumberOfPolys="0">"#).unwrap();
+        writeln!(xml, r#"      <PointData Scalars="mass" Vectors="velocity">"#).unwrap();
+        for array in &point_data {
+            data_array(&mut xml, array);
+        }
+        writeln!(xml, "      </PointData>").unwrap();
+        writeln!(xml, "      <Points>").unwrap();
+        data_array(&mut xml, &points);
+        writeln!(xml, "      </Points>").unwrap();
+        writeln!(xml, "      <Verts>").unwrap();
+        for array in &verts {
+            data_array(&mut xml, array);
+        }
+        writeln!(xml, "      </Verts>").unwrap();
+        writeln!(xml, "    </Piece>").unwrap();
+        writeln!(xml, "  </PolyData>").unwrap();
+        write!(xml, r#"  <AppendedData encoding="raw">"#).unwrap();
+        //the underscore marks where the data starts
+        write!(xml, "\n   _").unwrap();
+
+        writer.write_all(xml.as_bytes())?;
+        for (_, _, _, data) in point_data.iter().chain([&points]).chain(&verts) {
+            writer.write_all(&(data.len() as u32).to_le_bytes())?;
+            writer.write_all(data)?;
+        }
+        writer.write_all(b"\n  </AppendedData>\n</VTKFile>\n")
+    }
+}
+
+impl VtkSeries {
+    pub fn new(directory: impl Into<PathBuf>, name: impl Into<String>) -> Self {
+        Self {
+            directory: directory.into(),
+            name: name.into(),
+            started: false,
+        }
+    }
+
+    /// Writes a frame as `<name>_<frame>.vtp` and adds it to `<name>.pvd`.
+    pub fn add(&mut self, time: f32, frame_number: u32, frame: &VtkFrame) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.directory)?;
+        let file_name = format!("{}_{:0>5}.vtp", self.name, frame_number);
+        frame.save(self.directory.join(&file_name))?;
+
+        //the new entry replaces the closing tags, which get written again after it
+        let path = self.directory.join(format!("{}.pvd", self.name));
+        let mut collection = if self.started {
+            let mut file = File::options().write(true).open(path)?;
+            file.seek(SeekFrom::End(-(COLLECTION_END.len() as i64)))?;
+            BufWriter::new(file)
+        } else {
+            let mut collection = BufWriter::new(File::create(path)?);
+            writeln!(collection, r#"<?xml version="1.0"?>"#)?;
+            writeln!(collection, r#"<VTKFile type="Collection" version="1.0" byte_order="LittleEndian">"#)?;
+            writeln!(collection, "  <Collection>")?;
+            self.started = true;
+            collection
+        };
+        writeln!(collection, r#"    <DataSet timestep="{time}" group="" part="0" file="{file_name}"/>"#)?;
+        write!(collection, "{COLLECTION_END}")?;
+        collection.flush()
+    }
+}
+
+impl SimState {
+    /// Reads the live bodies with their ids and potentials back from the gpu.
+    pub async fn vtk_frame(&self, device: &Device, queue: &Queue) -> VtkFrame {
+        VtkFrame {
+            bodies: self.read_bodies(device, queue).await,
+            ids: self.read_ids(device, queue).await,
+            potentials: self.read_potentials(device, queue).await,
+        }
+    }
+}
+
+//the first n values, zeros if there are fewer
+fn padded<T: Copy + Default>(values: &[T], n: usize) -> impl Iterator<Item = T> + '_ {
+    values.iter().copied().chain(std::iter::repeat(T::default())).take(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collection_lists_every_frame() {
+        let directory = std::env::temp_dir().join(format!("vtk_series_{}", std::process::id()));
+        let mut series = VtkSeries::new(&directory, "frames");
+        let frame = VtkFrame {
+            bodies: vec![Body::new(1.0, [1.0, 2.0], [3.0, 4.0])],
+            ..Default::default()
+        };
+        for i in 0..3 {
+            series.add(i as f32 * 0.5, i, &frame).unwrap();
+        }
+
+        let collection = std::fs::read_to_string(directory.join("frames.pvd")).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        let expected = (0..3).map(|i| {
+            format!(r#"    <DataSet timestep="{}" group="" part="0" file="frames_{i:0>5}.vtp"/>"#, i as f32 * 0.5)
+        }).collect::<Vec<_>>().join("\n");
+        assert_eq!(collection, format!(
+            "<?xml version=\"1.0\"?>\n<VTKFile type=\"Collection\" version=\"1.0\" byte_order=\"LittleEndian\">\n  <Collection>\n{expected}\n{COLLECTION_END}"
+        ));
+    }
+}